@@ -0,0 +1,434 @@
+use std::collections::VecDeque;
+
+use bytes::Bytes;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::timeout,
+};
+
+use crate::{
+    error::{Error, Result},
+    frame::{
+        frame_decoder::FrameDecoder,
+        frame_types::{Frame, Opcode, PayloadLen},
+        message_assembler::MessageAssembler,
+    },
+    handshake::parse_response,
+    http_types::HttpStatus,
+    server::Message,
+    utils::{build_request, generate_accept, generate_mask, unmask_payload},
+    websocket_types::{
+        CloseCode, CloseFrame, RequestStruct, ResponseStruct, BUFFER_SIZE, CLOSE_TIMEOUT,
+        MAX_HANDSHAKE_SIZE, MAX_HEADERS, MAX_PAYLOAD_SIZE,
+    },
+};
+
+pub struct Client {
+    socket: TcpStream,
+    decoder: FrameDecoder,
+    assembler: MessageAssembler,
+    frames: VecDeque<Frame>,
+    close_sent: bool,
+    closed: bool,
+}
+
+impl Client {
+    pub async fn connect(url: &str) -> Result<Self> {
+        let (host, port, path): (String, u16, String) = parse_url(url)?;
+        let mut socket: TcpStream = TcpStream::connect((host.as_str(), port)).await?;
+
+        // IPv6 literals keep their brackets in the Host header
+        let authority: String = match host.contains(':') {
+            true => format!("[{}]:{}", host, port),
+            false => format!("{}:{}", host, port),
+        };
+
+        let mut request: RequestStruct = RequestStruct::client_default();
+        request.method = String::from("GET");
+        request.uri = path;
+        request.headers.insert(String::from("Host"), authority);
+        let key: String = request
+            .header("Sec-WebSocket-Key")
+            .cloned()
+            .unwrap_or_default();
+
+        socket.write_all(build_request(request).as_bytes()).await?;
+
+        let mut buffer: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE];
+        let mut data: Vec<u8> = Vec::new();
+        while !data.windows(4).any(|window| window == b"\r\n\r\n") {
+            if data.len() > MAX_HANDSHAKE_SIZE {
+                return Err(Error::Handshake(String::from("response too large")));
+            }
+
+            let n: usize = socket.read(&mut buffer).await?;
+            if n == 0 {
+                return Err(Error::Handshake(String::from(
+                    "connection closed during handshake",
+                )));
+            }
+            data.extend_from_slice(&buffer[..n]);
+        }
+
+        let (response, len): (ResponseStruct, usize) = parse_response(&data, MAX_HEADERS)?;
+        if !matches!(response.status, HttpStatus::SwitchingProtocols) {
+            return Err(Error::Handshake(response.status.as_str()));
+        }
+        if response.header("Sec-WebSocket-Accept") != Some(&generate_accept(key)) {
+            return Err(Error::Handshake(String::from(
+                "invalid Sec-WebSocket-Accept header",
+            )));
+        }
+
+        // The server may have sent its first frames along with the response
+        let mut decoder: FrameDecoder = FrameDecoder::new(MAX_PAYLOAD_SIZE).masked(false);
+        let frames: Vec<Frame> = decoder.feed(&data[len..])?;
+
+        Ok(Self {
+            socket,
+            decoder,
+            assembler: MessageAssembler::new(MAX_PAYLOAD_SIZE),
+            frames: frames.into(),
+            close_sent: false,
+            closed: false,
+        })
+    }
+
+    pub async fn send_string(&mut self, message: String) -> Result<()> {
+        self.send_frame(Opcode::Text, message.into_bytes()).await
+    }
+
+    pub async fn send_binary(&mut self, data: Vec<u8>) -> Result<()> {
+        self.send_frame(Opcode::Binary, data).await
+    }
+
+    pub async fn send_ping(&mut self, data: Vec<u8>) -> Result<()> {
+        if data.len() > 125 {
+            return Err(Error::PayloadTooLarge {
+                size: data.len(),
+                max_size: 125,
+            });
+        }
+        self.send_frame(Opcode::Ping, data).await
+    }
+
+    pub async fn close_with(
+        &mut self,
+        code: CloseCode,
+        reason: &str,
+    ) -> Result<Option<CloseFrame>> {
+        let close_frame: CloseFrame = CloseFrame::new(code, reason);
+        close_frame.check_code()?;
+        self.send_frame(Opcode::Close, close_frame.to_bytes())
+            .await?;
+
+        let result = timeout(CLOSE_TIMEOUT, async {
+            loop {
+                if let Message::Close(close_frame) = self.receive().await? {
+                    return Ok(close_frame);
+                }
+            }
+        })
+        .await;
+
+        match result {
+            Ok(close_frame) => close_frame,
+            Err(_) => {
+                self.closed = true;
+                let _ = self.socket.shutdown().await;
+                Ok(None)
+            }
+        }
+    }
+
+    pub async fn receive(&mut self) -> Result<Message> {
+        match self.next_message().await {
+            Err(err @ (Error::Protocol(_) | Error::Utf8(_) | Error::PayloadTooLarge { .. })) => {
+                let payload: Vec<u8> =
+                    CloseFrame::new(err.close_code(), &err.to_string()).to_bytes();
+                let _ = self.send_frame(Opcode::Close, payload).await;
+                self.closed = true;
+                let _ = self.socket.shutdown().await;
+                Err(err)
+            }
+            result => result,
+        }
+    }
+
+    async fn next_message(&mut self) -> Result<Message> {
+        let mut buffer: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE];
+
+        loop {
+            if self.closed {
+                return Err(Error::Closed);
+            }
+
+            let frame: Frame = match self.frames.pop_front() {
+                Some(frame) => frame,
+                None => {
+                    let n: usize = self.socket.read(&mut buffer).await?;
+                    if n == 0 {
+                        self.closed = true;
+                        return Err(Error::Closed);
+                    }
+                    self.frames.extend(self.decoder.feed(&buffer[..n])?);
+                    continue;
+                }
+            };
+
+            let (opcode, payload): (Opcode, Vec<u8>) = match self.assembler.push(frame)? {
+                Some(message) => message,
+                None => continue,
+            };
+
+            match opcode {
+                Opcode::Ping => {
+                    if !self.close_sent {
+                        self.send_frame(Opcode::Pong, payload.clone()).await?;
+                    }
+                    return Ok(Message::Ping(Bytes::from(payload)));
+                }
+                Opcode::Close => {
+                    let close_frame: Option<CloseFrame> = CloseFrame::from_bytes(&payload)?;
+
+                    // Echo the status code back to complete the closing handshake
+                    if !self.close_sent {
+                        let payload: Vec<u8> = match &close_frame {
+                            Some(close_frame) => CloseFrame::new(close_frame.code, "").to_bytes(),
+                            None => Vec::new(),
+                        };
+                        let _ = self.send_frame(Opcode::Close, payload).await;
+                    }
+
+                    self.closed = true;
+                    let _ = self.socket.shutdown().await;
+                    return Ok(Message::Close(close_frame));
+                }
+                _ => return Ok(Message::from_opcode(opcode, Bytes::from(payload))),
+            }
+        }
+    }
+
+    async fn send_frame(&mut self, opcode: Opcode, payload: Vec<u8>) -> Result<()> {
+        if self.close_sent || self.closed {
+            return Err(Error::Closed);
+        }
+        if opcode == Opcode::Close {
+            self.close_sent = true;
+        }
+
+        // Every frame sent by a client is masked with a fresh key
+        let masking_key: [u8; 4] = generate_mask();
+        let frame: Frame = Frame {
+            opcode,
+            mask: true,
+            masking_key: Some(masking_key),
+            payload_length: PayloadLen::from_size(payload.len()),
+            payload_data: Some(unmask_payload(&payload, &masking_key)),
+            ..Frame::default()
+        };
+
+        self.socket.write_all(&frame.to_bytes()).await?;
+        Ok(())
+    }
+}
+
+fn parse_url(url: &str) -> Result<(String, u16, String)> {
+    let rest: &str = url
+        .strip_prefix("ws://")
+        .ok_or_else(|| Error::Handshake(format!("unsupported url {}", url)))?;
+
+    let (authority, path): (&str, String) = match rest.find(['/', '?']) {
+        Some(i) if rest[i..].starts_with('?') => (&rest[..i], format!("/{}", &rest[i..])),
+        Some(i) => (&rest[..i], rest[i..].to_string()),
+        None => (rest, String::from("/")),
+    };
+
+    // IPv6 literals are written in brackets, e.g. ws://[::1]:8080/
+    let (host, port): (&str, Option<&str>) = match authority.strip_prefix('[') {
+        Some(bracketed) => match bracketed.split_once(']') {
+            Some((host, "")) => (host, None),
+            Some((host, rest)) => match rest.strip_prefix(':') {
+                Some(port) => (host, Some(port)),
+                None => return Err(Error::Handshake(format!("invalid host in {}", url))),
+            },
+            None => return Err(Error::Handshake(format!("invalid host in {}", url))),
+        },
+        None => match authority.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+    let port: u16 = match port {
+        Some(port) => port
+            .parse()
+            .map_err(|_| Error::Handshake(format!("invalid port in {}", url)))?,
+        None => 80,
+    };
+
+    if host.is_empty() {
+        return Err(Error::Handshake(format!("missing host in {}", url)));
+    }
+
+    Ok((host.to_string(), port, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, time::Duration};
+
+    use async_trait::async_trait;
+    use tokio::{net::TcpListener, sync::mpsc};
+
+    use super::*;
+    use crate::server::{EventHandler, Public, Server};
+
+    // Echoes data messages and reports how the client left
+    struct Echo(mpsc::UnboundedSender<Option<CloseFrame>>);
+
+    #[async_trait]
+    impl EventHandler for Echo {
+        type State = ();
+
+        async fn on_join(&self, _request: RequestStruct, _public: &mut Public) {}
+
+        async fn on_message(&self, _state: &mut (), public: &mut Public) {
+            if let Some(message @ (Message::Text(_) | Message::Binary(_))) = public.message.take() {
+                let _ = public.send(message).await;
+            }
+        }
+
+        async fn on_close(&self, _state: &mut (), close_frame: Option<CloseFrame>) {
+            let _ = self.0.send(close_frame);
+        }
+    }
+
+    async fn echo_server(
+        listener: TcpListener,
+    ) -> (SocketAddr, mpsc::UnboundedReceiver<Option<CloseFrame>>) {
+        let address: SocketAddr = listener.local_addr().unwrap();
+        let (sender, closed) = mpsc::unbounded_channel();
+
+        let mut server = Server::builder("")
+            .ping_interval(Duration::from_millis(50))
+            .build_with(listener);
+        server.set_handler(Box::new(Echo(sender)));
+        tokio::spawn(server.run());
+        (address, closed)
+    }
+
+    // The server pings on its own every 50ms, the client answers them itself
+    async fn receive(client: &mut Client, pinged: &mut bool) -> Message {
+        loop {
+            match client.receive().await.unwrap() {
+                Message::Ping(_) => *pinged = true,
+                message => return message,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn client_talks_to_the_server() {
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (address, mut closed) = echo_server(listener).await;
+        let mut client: Client = Client::connect(&format!("ws://{}/chat", address))
+            .await
+            .unwrap();
+
+        let mut pinged: bool = false;
+
+        client.send_string(String::from("hello")).await.unwrap();
+        assert_eq!(
+            receive(&mut client, &mut pinged).await,
+            Message::text("hello")
+        );
+
+        // Longer than what a 16 bit length can hold
+        let data: Vec<u8> = (0..70_000).map(|i| i as u8).collect();
+        client.send_binary(data.clone()).await.unwrap();
+        assert_eq!(
+            receive(&mut client, &mut pinged).await,
+            Message::binary(data)
+        );
+
+        client.send_ping(b"ping".to_vec()).await.unwrap();
+        let pong: Message = receive(&mut client, &mut pinged).await;
+        assert_eq!(pong, Message::Pong(Bytes::from_static(b"ping")));
+        while !pinged {
+            pinged = matches!(client.receive().await.unwrap(), Message::Ping(_));
+        }
+
+        let echoed: Option<CloseFrame> = client.close_with(CloseCode::Normal, "bye").await.unwrap();
+        assert_eq!(
+            echoed.map(|close_frame| close_frame.code),
+            Some(CloseCode::Normal)
+        );
+        let received: Option<CloseFrame> = closed.recv().await.unwrap();
+        assert_eq!(received, Some(CloseFrame::new(CloseCode::Normal, "bye")));
+        assert!(matches!(client.receive().await, Err(Error::Closed)));
+    }
+
+    #[tokio::test]
+    async fn client_connects_to_ipv6_literals() {
+        // Hosts without IPv6 have nothing to check
+        let listener: TcpListener = match TcpListener::bind("[::1]:0").await {
+            Ok(listener) => listener,
+            Err(_) => return,
+        };
+        let (address, _closed) = echo_server(listener).await;
+        let url: String = format!("ws://[::1]:{}/", address.port());
+        let mut client: Client = Client::connect(&url).await.unwrap();
+
+        let mut pinged: bool = false;
+        client.send_string(String::from("hello")).await.unwrap();
+        assert_eq!(
+            receive(&mut client, &mut pinged).await,
+            Message::text("hello")
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_accept_header_is_rejected() {
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address: SocketAddr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buffer: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE];
+            let _ = socket.read(&mut buffer).await;
+            let response: &[u8] = b"HTTP/1.1 101 Switching Protocols\r\n\
+                Upgrade: websocket\r\n\
+                Connection: Upgrade\r\n\
+                Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n";
+            let _ = socket.write_all(response).await;
+        });
+
+        let result = Client::connect(&format!("ws://{}/", address)).await;
+        assert!(matches!(result, Err(Error::Handshake(_))));
+    }
+
+    #[test]
+    fn urls_are_parsed() {
+        let parsed = |url: &str| parse_url(url).ok();
+        let expected =
+            |host: &str, port: u16, path: &str| Some((host.to_string(), port, path.to_string()));
+
+        assert_eq!(parsed("ws://localhost"), expected("localhost", 80, "/"));
+        assert_eq!(
+            parsed("ws://localhost:8080/chat?room=1"),
+            expected("localhost", 8080, "/chat?room=1")
+        );
+        assert_eq!(
+            parsed("ws://example.com?room=1"),
+            expected("example.com", 80, "/?room=1")
+        );
+        assert_eq!(parsed("ws://[::1]:8080/"), expected("::1", 8080, "/"));
+        assert_eq!(parsed("ws://[::1]/chat"), expected("::1", 80, "/chat"));
+
+        assert_eq!(parsed("wss://localhost"), None);
+        assert_eq!(parsed("ws://:8080"), None);
+        assert_eq!(parsed("ws://localhost:port"), None);
+        assert_eq!(parsed("ws://[::1/"), None);
+        assert_eq!(parsed("ws://[::1]8080/"), None);
+    }
+}
@@ -0,0 +1,95 @@
+use std::{fmt, io, str::Utf8Error, string::FromUtf8Error};
+
+use crate::{http_types::HttpStatus, websocket_types::CloseCode};
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Handshake(String),
+    HeadersTooLarge,
+    Protocol(String),
+    Utf8(Utf8Error),
+    PayloadTooLarge {
+        size: usize,
+        max_size: usize,
+    },
+    Closed,
+    #[cfg(feature = "tls")]
+    Tls(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn close_code(&self) -> CloseCode {
+        match self {
+            Error::Io(_) => CloseCode::InternalError,
+            Error::Handshake(_) | Error::HeadersTooLarge | Error::Protocol(_) => {
+                CloseCode::ProtocolError
+            }
+            Error::Utf8(_) => CloseCode::InvalidPayload,
+            Error::PayloadTooLarge { .. } => CloseCode::MessageTooBig,
+            Error::Closed => CloseCode::Normal,
+            #[cfg(feature = "tls")]
+            Error::Tls(_) => CloseCode::InternalError,
+        }
+    }
+
+    pub fn http_status(&self) -> HttpStatus {
+        match self {
+            Error::Io(_) => HttpStatus::InternalServerError,
+            Error::HeadersTooLarge => HttpStatus::RequestHeaderFieldsTooLarge,
+            _ => HttpStatus::BadRequest,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "io error: {}", err),
+            Error::Handshake(reason) => write!(f, "handshake failed: {}", reason),
+            Error::HeadersTooLarge => write!(f, "request headers too large"),
+            Error::Protocol(reason) => write!(f, "protocol error: {}", reason),
+            Error::Utf8(err) => write!(f, "invalid utf-8: {}", err),
+            Error::PayloadTooLarge { size, max_size } => {
+                write!(
+                    f,
+                    "payload of {} bytes exceeds the limit of {}",
+                    size, max_size
+                )
+            }
+            Error::Closed => write!(f, "connection closed"),
+            #[cfg(feature = "tls")]
+            Error::Tls(reason) => write!(f, "tls error: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Utf8(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<Utf8Error> for Error {
+    fn from(err: Utf8Error) -> Self {
+        Error::Utf8(err)
+    }
+}
+
+impl From<FromUtf8Error> for Error {
+    fn from(err: FromUtf8Error) -> Self {
+        Error::Utf8(err.utf8_error())
+    }
+}
@@ -0,0 +1,403 @@
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+
+use crate::{
+    error::{Error, Result},
+    frame::frame_types::{Frame, Opcode, PayloadLen},
+};
+
+use super::{Extension, ExtensionOffer, NegotiatedExtension, RsvBits};
+
+const EXTENSION_NAME: &str = "permessage-deflate";
+
+// Every compressed message ends with an empty stored block, which is
+// stripped before sending and added back before inflating (RFC 7692 7.2.1)
+const TRAILER: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+#[derive(Debug, Clone)]
+pub struct DeflateConfig {
+    pub server_no_context_takeover: bool,
+    pub client_no_context_takeover: bool,
+    pub server_max_window_bits: u8,
+    pub client_max_window_bits: u8,
+    pub level: u32,
+}
+
+impl Default for DeflateConfig {
+    fn default() -> Self {
+        Self {
+            server_no_context_takeover: false,
+            client_no_context_takeover: false,
+            server_max_window_bits: 15,
+            client_max_window_bits: 15,
+            level: Compression::default().level(),
+        }
+    }
+}
+
+impl Extension for DeflateConfig {
+    fn name(&self) -> &str {
+        EXTENSION_NAME
+    }
+
+    fn rsv_bits(&self) -> RsvBits {
+        RsvBits::RSV1
+    }
+
+    fn negotiate(
+        &self,
+        offer: &ExtensionOffer,
+        max_message_size: usize,
+    ) -> Option<(ExtensionOffer, Box<dyn NegotiatedExtension>)> {
+        let mut server_no_context_takeover: bool = self.server_no_context_takeover;
+        let mut client_no_context_takeover: bool = self.client_no_context_takeover;
+        let mut server_max_window_bits: Option<u8> = None;
+        let mut client_max_window_bits: Option<u8> = None;
+        let mut seen: Vec<&str> = Vec::new();
+
+        for (key, value) in &offer.params {
+            // A parameter given twice invalidates the whole offer
+            if seen.contains(&key.as_str()) {
+                return None;
+            }
+            seen.push(key);
+
+            match (key.as_str(), value) {
+                ("server_no_context_takeover", None) => server_no_context_takeover = true,
+                ("client_no_context_takeover", None) => client_no_context_takeover = true,
+                ("server_max_window_bits", Some(bits)) => {
+                    server_max_window_bits = Some(Self::window_bits(bits)?);
+                }
+                ("client_max_window_bits", None) => client_max_window_bits = Some(15),
+                ("client_max_window_bits", Some(bits)) => {
+                    client_max_window_bits = Some(Self::window_bits(bits)?);
+                }
+                _ => return None,
+            }
+        }
+
+        // zlib cannot produce raw deflate streams with a 256 byte window
+        let window_bits: u8 = server_max_window_bits
+            .unwrap_or(15)
+            .min(self.server_max_window_bits.clamp(9, 15));
+        if window_bits < 9 {
+            return None;
+        }
+
+        let mut response: ExtensionOffer = ExtensionOffer::new(EXTENSION_NAME);
+        if server_no_context_takeover {
+            response = response.param("server_no_context_takeover", None);
+        }
+        if client_no_context_takeover {
+            response = response.param("client_no_context_takeover", None);
+        }
+        if server_max_window_bits.is_some() {
+            response = response.param("server_max_window_bits", Some(window_bits.to_string()));
+        }
+        if let Some(bits) = client_max_window_bits {
+            let bits: u8 = bits.min(self.client_max_window_bits.clamp(8, 15));
+            if bits < 15 {
+                response = response.param("client_max_window_bits", Some(bits.to_string()));
+            }
+        }
+
+        let deflate: Deflate = Deflate {
+            compress: Compress::new_with_window_bits(
+                Compression::new(self.level.min(9)),
+                false,
+                window_bits,
+            ),
+            // A full window can inflate anything the client is allowed to send
+            decompress: Decompress::new_with_window_bits(false, 15),
+            server_no_context_takeover,
+            client_no_context_takeover,
+            max_message_size,
+            decoding: false,
+            decoded_len: 0,
+        };
+        Some((response, Box::new(deflate)))
+    }
+}
+
+impl DeflateConfig {
+    fn window_bits(value: &str) -> Option<u8> {
+        value
+            .parse::<u8>()
+            .ok()
+            .filter(|bits| (8..=15).contains(bits))
+    }
+}
+
+struct Deflate {
+    compress: Compress,
+    decompress: Decompress,
+    server_no_context_takeover: bool,
+    client_no_context_takeover: bool,
+    max_message_size: usize,
+    decoding: bool,
+    decoded_len: usize,
+}
+
+impl NegotiatedExtension for Deflate {
+    fn encode(&mut self, frame: &mut Frame) -> Result<()> {
+        if !matches!(frame.opcode, Opcode::Text | Opcode::Binary) {
+            return Ok(());
+        }
+
+        let payload: Vec<u8> = frame.payload_data.take().unwrap_or_default();
+        let mut output: Vec<u8> = Vec::with_capacity(payload.len() / 2 + 64);
+        let start: u64 = self.compress.total_in();
+
+        loop {
+            let consumed: usize = (self.compress.total_in() - start) as usize;
+            output.reserve(256.max(payload.len() - consumed));
+            self.compress
+                .compress_vec(&payload[consumed..], &mut output, FlushCompress::Sync)
+                .map_err(|err| Error::Protocol(format!("compression failed: {}", err)))?;
+
+            let consumed: usize = (self.compress.total_in() - start) as usize;
+            if consumed == payload.len() && output.len() < output.capacity() {
+                break;
+            }
+        }
+
+        if output.ends_with(&TRAILER) {
+            output.truncate(output.len() - TRAILER.len());
+        }
+        if self.server_no_context_takeover {
+            self.compress.reset();
+        }
+
+        frame.rsv1 = true;
+        frame.payload_length = PayloadLen::from_size(output.len());
+        frame.payload_data = Some(output);
+        Ok(())
+    }
+
+    // Fragments are inflated as they arrive, RSV1 is only set on the first one
+    fn decode(&mut self, frame: &mut Frame) -> Result<()> {
+        match frame.opcode {
+            Opcode::Text | Opcode::Binary => {
+                self.decoding = frame.rsv1;
+                self.decoded_len = 0;
+            }
+            Opcode::Continuation if frame.rsv1 => {
+                return Err(Error::Protocol(String::from(
+                    "rsv1 set on a continuation frame",
+                )));
+            }
+            Opcode::Continuation => {}
+            _ if frame.rsv1 => {
+                return Err(Error::Protocol(String::from("rsv1 set on a control frame")));
+            }
+            _ => return Ok(()),
+        }
+
+        if !self.decoding {
+            return Ok(());
+        }
+
+        let payload: Vec<u8> = frame.payload_data.take().unwrap_or_default();
+        let mut output: Vec<u8> = Vec::with_capacity(payload.len() * 2);
+        self.inflate(&payload, &mut output)?;
+
+        if frame.is_final {
+            self.inflate(&TRAILER, &mut output)?;
+            self.decoding = false;
+            if self.client_no_context_takeover {
+                self.decompress.reset(false);
+            }
+        }
+
+        self.decoded_len += output.len();
+        frame.rsv1 = false;
+        frame.payload_length = PayloadLen::from_size(output.len());
+        frame.payload_data = Some(output);
+        Ok(())
+    }
+}
+
+impl Deflate {
+    fn inflate(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<()> {
+        let start: u64 = self.decompress.total_in();
+
+        loop {
+            let consumed: usize = (self.decompress.total_in() - start) as usize;
+            output.reserve(1024.max(input.len() - consumed));
+            let status: Status = self
+                .decompress
+                .decompress_vec(&input[consumed..], output, FlushDecompress::Sync)
+                .map_err(|err| Error::Protocol(format!("invalid compressed data: {}", err)))?;
+
+            // Checked while inflating so a small frame cannot expand without bound
+            let size: usize = self.decoded_len + output.len();
+            if size > self.max_message_size {
+                return Err(Error::PayloadTooLarge {
+                    size,
+                    max_size: self.max_message_size,
+                });
+            }
+
+            let consumed: usize = (self.decompress.total_in() - start) as usize;
+            let done: bool = consumed == input.len() && output.len() < output.capacity();
+            if done || status == Status::StreamEnd {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn negotiate(header: &str) -> Option<(String, Box<dyn NegotiatedExtension>)> {
+        let offer: ExtensionOffer = ExtensionOffer::parse(header).remove(0);
+        DeflateConfig::default()
+            .negotiate(&offer, 1 << 20)
+            .map(|(response, deflate)| (response.to_string(), deflate))
+    }
+
+    fn frame(opcode: Opcode, is_final: bool, rsv1: bool, payload: Vec<u8>) -> Frame {
+        Frame {
+            is_final,
+            rsv1,
+            opcode,
+            payload_length: PayloadLen::from_size(payload.len()),
+            payload_data: Some(payload),
+            ..Frame::default()
+        }
+    }
+
+    // What a client with its own zlib would send, trailer stripped
+    fn peer_compress(compress: &mut Compress, data: &[u8]) -> Vec<u8> {
+        let mut output: Vec<u8> = Vec::with_capacity(data.len() + 64);
+        compress
+            .compress_vec(data, &mut output, FlushCompress::Sync)
+            .unwrap();
+        assert!(output.ends_with(&TRAILER));
+        output.truncate(output.len() - TRAILER.len());
+        output
+    }
+
+    fn peer_decompress(decompress: &mut Decompress, data: &[u8]) -> Vec<u8> {
+        let input: Vec<u8> = [data, &TRAILER].concat();
+        let mut output: Vec<u8> = Vec::with_capacity(4096);
+        decompress
+            .decompress_vec(&input, &mut output, FlushDecompress::Sync)
+            .unwrap();
+        output
+    }
+
+    #[test]
+    fn duplicate_parameters_are_declined() {
+        assert!(negotiate(
+            "permessage-deflate; server_no_context_takeover; server_no_context_takeover"
+        )
+        .is_none());
+        assert!(
+            negotiate("permessage-deflate; client_max_window_bits; client_max_window_bits=10")
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn window_bits_are_checked() {
+        // zlib has no raw deflate with a 256 byte window
+        assert!(negotiate("permessage-deflate; server_max_window_bits=8").is_none());
+        assert!(negotiate("permessage-deflate; server_max_window_bits=16").is_none());
+        assert!(negotiate("permessage-deflate; client_max_window_bits=7").is_none());
+        assert!(negotiate("permessage-deflate; unknown_param").is_none());
+
+        let (response, _) = negotiate("permessage-deflate; server_max_window_bits=10").unwrap();
+        assert_eq!(response, "permessage-deflate; server_max_window_bits=10");
+    }
+
+    #[test]
+    fn client_max_window_bits_without_value() {
+        // The full window is used, which needs no answer
+        let (response, _) = negotiate("permessage-deflate; client_max_window_bits").unwrap();
+        assert_eq!(response, "permessage-deflate");
+
+        let (response, _) = negotiate("permessage-deflate; client_max_window_bits=12").unwrap();
+        assert_eq!(response, "permessage-deflate; client_max_window_bits=12");
+    }
+
+    #[test]
+    fn encode_is_read_by_a_plain_zlib_peer() {
+        let (_, mut deflate) = negotiate("permessage-deflate").unwrap();
+        let mut peer: Decompress = Decompress::new(false);
+
+        // The second message relies on the context kept from the first
+        for text in ["hello hello hello", "hello hello hello again"] {
+            let mut sent: Frame = frame(Opcode::Text, true, false, text.as_bytes().to_vec());
+            deflate.encode(&mut sent).unwrap();
+            assert!(sent.rsv1);
+
+            let payload: Vec<u8> = sent.payload_data.unwrap();
+            assert_eq!(peer_decompress(&mut peer, &payload), text.as_bytes());
+        }
+
+        // Control frames are left untouched
+        let mut ping: Frame = frame(Opcode::Ping, true, false, b"ping".to_vec());
+        deflate.encode(&mut ping).unwrap();
+        assert!(!ping.rsv1);
+        assert_eq!(ping.payload_data.unwrap(), b"ping");
+    }
+
+    #[test]
+    fn decode_reads_a_plain_zlib_peer() {
+        let (_, mut deflate) = negotiate("permessage-deflate").unwrap();
+        let mut peer: Compress = Compress::new(Compression::default(), false);
+
+        for text in ["first message", "first message, then a second one"] {
+            let payload: Vec<u8> = peer_compress(&mut peer, text.as_bytes());
+            let mut received: Frame = frame(Opcode::Text, true, true, payload);
+            deflate.decode(&mut received).unwrap();
+            assert!(!received.rsv1);
+            assert_eq!(received.payload_data.unwrap(), text.as_bytes());
+        }
+
+        // A compressed message split in two fragments, RSV1 only on the first
+        let payload: Vec<u8> = peer_compress(&mut peer, b"fragmented message");
+        let (head, tail) = payload.split_at(payload.len() / 2);
+        let mut first: Frame = frame(Opcode::Binary, false, true, head.to_vec());
+        let mut last: Frame = frame(Opcode::Continuation, true, false, tail.to_vec());
+        deflate.decode(&mut first).unwrap();
+        deflate.decode(&mut last).unwrap();
+        let decoded: Vec<u8> = [first.payload_data.unwrap(), last.payload_data.unwrap()].concat();
+        assert_eq!(decoded, b"fragmented message");
+
+        // Uncompressed messages pass through
+        let mut plain: Frame = frame(Opcode::Text, true, false, b"plain".to_vec());
+        deflate.decode(&mut plain).unwrap();
+        assert_eq!(plain.payload_data.unwrap(), b"plain");
+    }
+
+    #[test]
+    fn invalid_rsv1_is_rejected() {
+        let (_, mut deflate) = negotiate("permessage-deflate").unwrap();
+
+        let mut continuation: Frame = frame(Opcode::Continuation, true, true, Vec::new());
+        assert!(matches!(
+            deflate.decode(&mut continuation),
+            Err(Error::Protocol(_))
+        ));
+
+        let mut ping: Frame = frame(Opcode::Ping, true, true, Vec::new());
+        assert!(matches!(deflate.decode(&mut ping), Err(Error::Protocol(_))));
+    }
+
+    #[test]
+    fn inflated_size_is_limited() {
+        let offer: ExtensionOffer = ExtensionOffer::new(EXTENSION_NAME);
+        let (_, mut deflate) = DeflateConfig::default().negotiate(&offer, 1024).unwrap();
+        let mut peer: Compress = Compress::new(Compression::default(), false);
+
+        let payload: Vec<u8> = peer_compress(&mut peer, &[0; 4096]);
+        let mut received: Frame = frame(Opcode::Binary, true, true, payload);
+        assert!(matches!(
+            deflate.decode(&mut received),
+            Err(Error::PayloadTooLarge { max_size: 1024, .. })
+        ));
+    }
+}
@@ -0,0 +1,72 @@
+use crate::{error::Result, frame::frame_types::Frame};
+
+use super::ExtensionOffer;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RsvBits {
+    pub rsv1: bool,
+    pub rsv2: bool,
+    pub rsv3: bool,
+}
+
+impl RsvBits {
+    pub const NONE: Self = Self::new(false, false, false);
+    pub const RSV1: Self = Self::new(true, false, false);
+    pub const RSV2: Self = Self::new(false, true, false);
+    pub const RSV3: Self = Self::new(false, false, true);
+
+    pub const fn new(rsv1: bool, rsv2: bool, rsv3: bool) -> Self {
+        Self { rsv1, rsv2, rsv3 }
+    }
+
+    pub fn of(frame: &Frame) -> Self {
+        Self::new(frame.rsv1, frame.rsv2, frame.rsv3)
+    }
+
+    pub fn union(self, other: Self) -> Self {
+        Self::new(
+            self.rsv1 || other.rsv1,
+            self.rsv2 || other.rsv2,
+            self.rsv3 || other.rsv3,
+        )
+    }
+
+    pub fn intersects(self, other: Self) -> bool {
+        (self.rsv1 && other.rsv1) || (self.rsv2 && other.rsv2) || (self.rsv3 && other.rsv3)
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.union(other) == self
+    }
+}
+
+// Registered on the server and asked to accept the client's offers during
+// the handshake. Each accepted offer yields a state owned by the connection.
+pub trait Extension: Send + Sync {
+    // Token matched against the offers of Sec-WebSocket-Extensions
+    fn name(&self) -> &str;
+
+    // Bits this extension may set, frames using any other bit are rejected
+    fn rsv_bits(&self) -> RsvBits {
+        RsvBits::NONE
+    }
+
+    // Returns the parameters echoed to the client, or None to decline the offer
+    fn negotiate(
+        &self,
+        offer: &ExtensionOffer,
+        max_message_size: usize,
+    ) -> Option<(ExtensionOffer, Box<dyn NegotiatedExtension>)>;
+}
+
+pub trait NegotiatedExtension: Send {
+    // Called on every frame before it is written
+    fn encode(&mut self, _frame: &mut Frame) -> Result<()> {
+        Ok(())
+    }
+
+    // Called on every frame read, before fragments are reassembled
+    fn decode(&mut self, _frame: &mut Frame) -> Result<()> {
+        Ok(())
+    }
+}
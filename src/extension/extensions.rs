@@ -0,0 +1,84 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::{
+    error::{Error, Result},
+    frame::frame_types::Frame,
+};
+
+use super::{Extension, ExtensionOffer, NegotiatedExtension, RsvBits};
+
+// Extensions negotiated for one connection, shared by its reader and writer
+#[derive(Default, Clone)]
+pub(crate) struct Extensions {
+    negotiated: Arc<Mutex<Vec<Box<dyn NegotiatedExtension>>>>,
+    rsv_bits: RsvBits,
+}
+
+impl Extensions {
+    // Extensions are tried in the server's order, each one against the
+    // client's offers for it in the client's order
+    pub(crate) fn negotiate(
+        available: &[Arc<dyn Extension>],
+        offers: &[ExtensionOffer],
+        max_message_size: usize,
+    ) -> (Self, Vec<ExtensionOffer>) {
+        let mut negotiated: Vec<Box<dyn NegotiatedExtension>> = Vec::new();
+        let mut accepted: Vec<ExtensionOffer> = Vec::new();
+        let mut rsv_bits: RsvBits = RsvBits::NONE;
+
+        for extension in available {
+            // Two extensions cannot own the same bit
+            if rsv_bits.intersects(extension.rsv_bits()) {
+                continue;
+            }
+
+            let found = offers
+                .iter()
+                .filter(|offer| offer.name.eq_ignore_ascii_case(extension.name()))
+                .find_map(|offer| extension.negotiate(offer, max_message_size));
+
+            if let Some((response, state)) = found {
+                rsv_bits = rsv_bits.union(extension.rsv_bits());
+                accepted.push(response);
+                negotiated.push(state);
+            }
+        }
+
+        let extensions: Extensions = Self {
+            negotiated: Arc::new(Mutex::new(negotiated)),
+            rsv_bits,
+        };
+        (extensions, accepted)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    pub(crate) fn encode(&self, frame: &mut Frame) -> Result<()> {
+        for extension in self.lock().iter_mut() {
+            extension.encode(frame)?;
+        }
+        Ok(())
+    }
+
+    // Incoming frames go through the extensions in reverse order
+    pub(crate) fn decode(&self, frame: &mut Frame) -> Result<()> {
+        if !self.rsv_bits.contains(RsvBits::of(frame)) {
+            return Err(Error::Protocol(String::from(
+                "reserved bits set without a negotiated extension",
+            )));
+        }
+
+        for extension in self.lock().iter_mut().rev() {
+            extension.decode(frame)?;
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<Box<dyn NegotiatedExtension>>> {
+        self.negotiated
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+}
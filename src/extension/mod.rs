@@ -0,0 +1,11 @@
+#[cfg(feature = "deflate")]
+mod deflate;
+#[allow(clippy::module_inception)]
+mod extension;
+mod extensions;
+mod offer;
+#[cfg(feature = "deflate")]
+pub use deflate::*;
+pub use extension::*;
+pub(crate) use extensions::*;
+pub use offer::*;
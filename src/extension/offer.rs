@@ -0,0 +1,61 @@
+use std::fmt;
+
+// One entry of a Sec-WebSocket-Extensions header, e.g.
+// `permessage-deflate; client_max_window_bits; server_max_window_bits=10`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtensionOffer {
+    pub name: String,
+    pub params: Vec<(String, Option<String>)>,
+}
+
+impl ExtensionOffer {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            params: Vec::new(),
+        }
+    }
+
+    pub fn param(mut self, name: &str, value: Option<String>) -> Self {
+        self.params.push((name.to_string(), value));
+        self
+    }
+
+    pub fn parse(header: &str) -> Vec<Self> {
+        header
+            .split(',')
+            .filter_map(|offer| {
+                let mut parts = offer.split(';').map(str::trim);
+                let name: &str = parts.next().filter(|name| !name.is_empty())?;
+                let params: Vec<(String, Option<String>)> = parts
+                    .filter(|param| !param.is_empty())
+                    .map(|param| match param.split_once('=') {
+                        Some((key, value)) => (
+                            key.trim().to_ascii_lowercase(),
+                            Some(value.trim().trim_matches('"').to_string()),
+                        ),
+                        None => (param.to_ascii_lowercase(), None),
+                    })
+                    .collect();
+
+                Some(Self {
+                    name: name.to_ascii_lowercase(),
+                    params,
+                })
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for ExtensionOffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        for (key, value) in &self.params {
+            match value {
+                Some(value) => write!(f, "; {}={}", key, value)?,
+                None => write!(f, "; {}", key)?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,248 @@
+use crate::error::{Error, Result};
+
+use super::frame_types::{Frame, Opcode, PayloadLen};
+
+// Control frames cannot carry more than this
+const MAX_CONTROL_PAYLOAD_SIZE: usize = 125;
+
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+    max_payload_size: usize,
+    masked: Option<bool>,
+}
+
+impl FrameDecoder {
+    pub fn new(max_payload_size: usize) -> Self {
+        Self {
+            buffer: Vec::new(),
+            max_payload_size,
+            masked: None,
+        }
+    }
+
+    // Frames sent by a client must be masked and frames sent by a server must not
+    pub fn masked(mut self, masked: bool) -> Self {
+        self.masked = Some(masked);
+        self
+    }
+
+    pub fn feed(&mut self, data: &[u8]) -> Result<Vec<Frame>> {
+        self.buffer.extend_from_slice(data);
+
+        let mut frames: Vec<Frame> = Vec::new();
+        while let Some(frame) = self.decode()? {
+            frames.push(frame);
+        }
+        Ok(frames)
+    }
+
+    pub fn decode(&mut self) -> Result<Option<Frame>> {
+        let header_len: usize = match self.header_len() {
+            Some(len) if self.buffer.len() >= len => len,
+            _ => return Ok(None),
+        };
+
+        let mut frame: Frame = Frame::default();
+        frame.default_header(self.buffer[..header_len].to_vec());
+        self.validate(&frame)?;
+
+        let payload_len: usize = match frame.payload_length.clone() {
+            PayloadLen::Unknow => {
+                return Err(Error::Protocol(String::from("invalid payload length")))
+            }
+            // A length that does not fit in usize is over any limit
+            len => TryInto::<usize>::try_into(len).unwrap_or(usize::MAX),
+        };
+        if payload_len > self.max_payload_size {
+            return Err(Error::PayloadTooLarge {
+                size: payload_len,
+                max_size: self.max_payload_size,
+            });
+        }
+
+        if self.buffer.len() < header_len + payload_len {
+            return Ok(None);
+        }
+
+        let data: Vec<u8> = self.buffer.drain(..header_len + payload_len).collect();
+        frame.default_from(data);
+        Ok(Some(frame))
+    }
+
+    // Checked on the header alone, before the payload is received
+    fn validate(&self, frame: &Frame) -> Result<()> {
+        if frame.opcode == Opcode::Unknow {
+            return Err(Error::Protocol(format!(
+                "reserved opcode {:#x}",
+                self.buffer[0] & 0x0F
+            )));
+        }
+
+        match self.masked {
+            Some(true) if !frame.mask => {
+                return Err(Error::Protocol(String::from("unmasked client frame")));
+            }
+            Some(false) if frame.mask => {
+                return Err(Error::Protocol(String::from("masked server frame")));
+            }
+            _ => {}
+        }
+
+        if matches!(frame.opcode, Opcode::Close | Opcode::Ping | Opcode::Pong) {
+            if !frame.is_final {
+                return Err(Error::Protocol(String::from("fragmented control frame")));
+            }
+
+            let payload_len: usize =
+                TryInto::<usize>::try_into(frame.payload_length.clone()).unwrap_or(usize::MAX);
+            if payload_len > MAX_CONTROL_PAYLOAD_SIZE {
+                return Err(Error::Protocol(format!(
+                    "control frame payload of {} bytes exceeds {}",
+                    payload_len, MAX_CONTROL_PAYLOAD_SIZE
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn header_len(&self) -> Option<usize> {
+        if self.buffer.len() < 2 {
+            return None;
+        }
+
+        let extended_length: usize = match self.buffer[1] & 0x7F {
+            126 => 2,
+            127 => 8,
+            _ => 0,
+        };
+        let masking_key_length: usize = if self.buffer[1] & 0x80 != 0 { 4 } else { 0 };
+
+        Some(2 + extended_length + masking_key_length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(first: u8, mask: bool, len: usize) -> Vec<u8> {
+        let mask_bit: u8 = if mask { 0x80 } else { 0 };
+        let mut bytes: Vec<u8> = vec![first];
+        match len {
+            0..=125 => bytes.push(mask_bit | len as u8),
+            126..=65535 => {
+                bytes.push(mask_bit | 126);
+                bytes.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            _ => {
+                bytes.push(mask_bit | 127);
+                bytes.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        bytes
+    }
+
+    fn masked_frame(first: u8, payload: &[u8]) -> Vec<u8> {
+        let key: [u8; 4] = [1, 2, 3, 4];
+        let mut bytes: Vec<u8> = header(first, true, payload.len());
+        bytes.extend_from_slice(&key);
+        bytes.extend(payload.iter().enumerate().map(|(i, b)| b ^ key[i % 4]));
+        bytes
+    }
+
+    #[test]
+    fn header_split_across_feeds() {
+        let payload: Vec<u8> = vec![7; 70_000];
+        let mut data: Vec<u8> = header(0x82, false, payload.len());
+        data.extend_from_slice(&payload);
+
+        let mut decoder: FrameDecoder = FrameDecoder::new(100_000);
+        // Marker, then every byte of the 64-bit length one at a time
+        for byte in &data[..10] {
+            assert!(decoder.feed(&[*byte]).unwrap().is_empty());
+        }
+        assert!(decoder.feed(&data[10..100]).unwrap().is_empty());
+
+        let frames: Vec<Frame> = decoder.feed(&data[100..]).unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].opcode, Opcode::Binary);
+        assert_eq!(frames[0].payload_length, PayloadLen::LengthU64(70_000));
+        assert_eq!(frames[0].payload_data.as_deref(), Some(&payload[..]));
+    }
+
+    #[test]
+    fn two_frames_in_one_chunk() {
+        let mut data: Vec<u8> = masked_frame(0x01, b"hel");
+        data.extend(masked_frame(0x80, b"lo"));
+
+        let frames: Vec<Frame> = FrameDecoder::new(1024).masked(true).feed(&data).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert!(!frames[0].is_final);
+        assert_eq!(frames[0].payload_data.as_deref(), Some(&b"hel"[..]));
+        assert_eq!(frames[1].opcode, Opcode::Continuation);
+        assert_eq!(frames[1].payload_data.as_deref(), Some(&b"lo"[..]));
+    }
+
+    #[test]
+    fn mask_is_enforced() {
+        let mut unmasked: Vec<u8> = header(0x81, false, 2);
+        unmasked.extend_from_slice(b"hi");
+        let masked: Vec<u8> = masked_frame(0x81, b"hi");
+
+        assert!(matches!(
+            FrameDecoder::new(1024).masked(true).feed(&unmasked),
+            Err(Error::Protocol(_))
+        ));
+        assert!(matches!(
+            FrameDecoder::new(1024).masked(false).feed(&masked),
+            Err(Error::Protocol(_))
+        ));
+        assert_eq!(
+            FrameDecoder::new(1024)
+                .masked(false)
+                .feed(&unmasked)
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(FrameDecoder::new(1024).feed(&masked).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn length_with_most_significant_bit_set() {
+        let mut data: Vec<u8> = vec![0x82, 127];
+        data.extend_from_slice(&(1u64 << 63).to_be_bytes());
+
+        assert!(matches!(
+            FrameDecoder::new(1024).feed(&data),
+            Err(Error::Protocol(_))
+        ));
+    }
+
+    #[test]
+    fn length_over_the_limit() {
+        let data: Vec<u8> = header(0x82, false, 70_000);
+
+        assert!(matches!(
+            FrameDecoder::new(1024).feed(&data),
+            Err(Error::PayloadTooLarge {
+                size: 70_000,
+                max_size: 1024
+            })
+        ));
+    }
+
+    #[test]
+    fn invalid_control_frames() {
+        let fragmented_ping: Vec<u8> = masked_frame(0x09, b"");
+        let long_ping: Vec<u8> = masked_frame(0x89, &[0; 126]);
+        let reserved_opcode: Vec<u8> = masked_frame(0x83, b"");
+
+        for data in [fragmented_ping, long_ping, reserved_opcode] {
+            assert!(matches!(
+                FrameDecoder::new(1024).masked(true).feed(&data),
+                Err(Error::Protocol(_))
+            ));
+        }
+    }
+}
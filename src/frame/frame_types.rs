@@ -59,7 +59,9 @@ impl TryInto<usize> for PayloadLen {
         match self {
             PayloadLen::LengthU8(len) => Ok(len as usize),
             PayloadLen::LengthU16(len) => Ok(len as usize),
-            PayloadLen::LengthU64(len) => Ok(len as usize),
+            PayloadLen::LengthU64(len) => {
+                usize::try_from(len).map_err(|_| "Length does not fit in usize")
+            }
             PayloadLen::Unknow => Err("Conversion failed"),
         }
     }
@@ -83,11 +85,7 @@ impl PayloadLen {
         match len {
             0..=125 => Self::LengthU8(len.try_into().unwrap()),
             126..=65535 => Self::LengthU16(len.try_into().unwrap()),
-
-            // TODO: With 64bit
-            _ => {
-                unimplemented!()
-            }
+            _ => Self::LengthU64(len as u64),
         }
     }
 }
@@ -124,37 +122,11 @@ impl Frame {
         self.rsv3 = first_bits[3] != 0;
         self.opcode = Opcode::with_bits(first_bits[4..8].try_into().unwrap());
 
-        if self.opcode == Opcode::Close {
-            return Self {
-                is_final: true,
-                rsv1: false,
-                rsv2: false,
-                rsv3: false,
-                opcode: Opcode::Close,
-                mask: false,
-                payload_length: PayloadLen::LengthU8(16),
-                masking_key: None,
-                payload_data: None,
-            };
-        } else if self.opcode == Opcode::Ping {
-            return Self {
-                is_final: true,
-                rsv1: false,
-                rsv2: false,
-                rsv3: false,
-                opcode: Opcode::Pong,
-                mask: false,
-                masking_key: None,
-                payload_length: PayloadLen::LengthU8(0),
-                payload_data: None,
-            };
-        }
-
         self.mask = second_bits[0] != 0;
         let payload_len: PayloadLen = PayloadLen::with_size(second_bits[1..8].try_into().unwrap());
 
         match payload_len {
-            PayloadLen::LengthU8(_) => {
+            PayloadLen::LengthU8(_) | PayloadLen::Unknow => {
                 self.payload_length = payload_len;
             }
 
@@ -166,47 +138,49 @@ impl Frame {
                 self.payload_length = PayloadLen::LengthU16(length);
             }
 
-            // TODO
             PayloadLen::LengthU64(_) => {
-                unimplemented!();
-            }
-
-            // TODO
-            _ => {
-                unimplemented!();
+                let length: u64 = u64::from_be_bytes(data[2..10].try_into().unwrap());
+
+                // The most significant bit must be 0 (RFC 6455 section 5.2)
+                self.payload_length = if length >> 63 == 0 {
+                    PayloadLen::LengthU64(length)
+                } else {
+                    PayloadLen::Unknow
+                };
             }
         }
         self.clone()
     }
     pub fn default_from(&mut self, data: Vec<u8>) -> Self {
-        match self.payload_length {
-            PayloadLen::LengthU8(_) => {
-                self.masking_key = Some(data[2..6].try_into().unwrap());
-                self.payload_data = Some(unmask_payload(&data[6..], &self.masking_key.unwrap()))
-            }
+        let header_len: usize = self.header_len();
+        let payload_len: usize = TryInto::<usize>::try_into(self.payload_length.clone()).unwrap();
+        let payload: &[u8] = &data[header_len..header_len + payload_len];
 
-            PayloadLen::LengthU16(_) => {
-                self.masking_key = Some(data[4..8].try_into().unwrap());
-                self.payload_data = Some(unmask_payload(&data[8..], &self.masking_key.unwrap()));
-            }
-
-            // TODO
-            PayloadLen::LengthU64(_) => {
-                unimplemented!();
-            }
-
-            // TODO
-            _ => {
-                unimplemented!();
-            }
+        if self.mask {
+            self.masking_key = Some(data[header_len - 4..header_len].try_into().unwrap());
+            self.payload_data = Some(unmask_payload(payload, &self.masking_key.unwrap()));
+        } else {
+            self.payload_data = Some(payload.to_vec());
         }
 
         self.clone()
     }
+    // Never fails: a missing payload is written as an empty one, and without a
+    // masking key the frame is written unmasked
     pub fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes: Vec<u8> = Vec::new();
+        let payload: &[u8] = self.payload_data.as_deref().unwrap_or_default();
+        let mut bytes: Vec<u8> = self.header_bytes(payload.len());
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    // Everything written before a payload of the given length, the payload must
+    // already be masked with `masking_key`
+    pub fn header_bytes(&self, payload_len: usize) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::with_capacity(14);
         let mut first_octal: u8 = 0;
         let mut second_octal: u8 = 0;
+        let masking_key: Option<[u8; 4]> = self.masking_key.filter(|_| self.mask);
 
         first_octal |= if self.is_final { 1 } else { 0 } << 7;
         first_octal |= if self.rsv1 { 1 } else { 0 } << 6;
@@ -214,38 +188,45 @@ impl Frame {
         first_octal |= if self.rsv3 { 1 } else { 0 } << 4;
         first_octal |= self.opcode.to_bytes();
 
-        second_octal |= if self.mask { 1 } else { 0 } << 7;
-        let mut byte_payload_length: Vec<u8> = Vec::new();
+        second_octal |= if masking_key.is_some() { 1 } else { 0 } << 7;
         bytes.push(first_octal);
 
-        match self.payload_length {
+        // The length always matches the payload actually written
+        match PayloadLen::from_size(payload_len) {
             PayloadLen::LengthU8(len) => {
                 second_octal |= len;
                 bytes.push(second_octal);
             }
             PayloadLen::LengthU16(len) => {
-                byte_payload_length = len.to_be_bytes().to_vec();
                 second_octal |= 126_u8;
                 bytes.push(second_octal);
-                bytes.append(&mut byte_payload_length);
-            }
-            // TODO
-            PayloadLen::LengthU64(_) => {
-                unimplemented!();
+                bytes.extend_from_slice(&len.to_be_bytes());
             }
-            // TODO
-            _ => {
-                unimplemented!();
+            PayloadLen::LengthU64(len) => {
+                second_octal |= 127_u8;
+                bytes.push(second_octal);
+                bytes.extend_from_slice(&len.to_be_bytes());
             }
+            // Never returned by from_size
+            PayloadLen::Unknow => {}
         }
 
-        if self.mask {
-            bytes.append(&mut self.masking_key.unwrap().try_into().unwrap());
+        if let Some(masking_key) = masking_key {
+            bytes.extend_from_slice(&masking_key);
         }
-
-        bytes.append(&mut self.payload_data.clone().unwrap().try_into().unwrap());
         bytes
     }
+
+    pub fn header_len(&self) -> usize {
+        let extended_length: usize = match self.payload_length {
+            PayloadLen::LengthU16(_) => 2,
+            PayloadLen::LengthU64(_) => 8,
+            _ => 0,
+        };
+        let masking_key_length: usize = if self.mask { 4 } else { 0 };
+
+        2 + extended_length + masking_key_length
+    }
 }
 
 impl Default for Frame {
@@ -263,3 +244,48 @@ impl Default for Frame {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_bytes_without_payload_or_key() {
+        let frame: Frame = Frame {
+            opcode: Opcode::Binary,
+            payload_data: None,
+            ..Frame::default()
+        };
+        assert_eq!(frame.to_bytes(), [0x82, 0x00]);
+
+        // The mask bit is only set along with a key
+        let frame: Frame = Frame {
+            opcode: Opcode::Text,
+            mask: true,
+            payload_data: Some(b"hi".to_vec()),
+            ..Frame::default()
+        };
+        assert_eq!(frame.to_bytes(), b"\x81\x02hi");
+
+        let frame: Frame = Frame {
+            masking_key: Some([1, 2, 3, 4]),
+            ..frame
+        };
+        assert_eq!(frame.to_bytes(), b"\x81\x82\x01\x02\x03\x04hi");
+
+        assert_eq!(Frame::default().to_bytes(), [0x8f, 0x00]);
+    }
+
+    #[test]
+    fn header_of_long_payloads() {
+        let frame: Frame = Frame {
+            opcode: Opcode::Binary,
+            ..Frame::default()
+        };
+        assert_eq!(frame.header_bytes(126), [0x82, 126, 0x00, 126]);
+        assert_eq!(
+            frame.header_bytes(65536),
+            [0x82, 127, 0, 0, 0, 0, 0, 1, 0, 0]
+        );
+    }
+}
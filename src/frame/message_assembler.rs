@@ -0,0 +1,196 @@
+use crate::error::{Error, Result};
+
+use super::frame_types::{Frame, Opcode};
+
+pub struct MessageAssembler {
+    opcode: Option<Opcode>,
+    data: Vec<u8>,
+    max_message_size: usize,
+    validate_utf8: bool,
+    // Length of the text already known to be valid UTF-8
+    utf8_checked: usize,
+}
+
+impl MessageAssembler {
+    pub fn new(max_message_size: usize) -> Self {
+        Self {
+            opcode: None,
+            data: Vec::new(),
+            max_message_size,
+            validate_utf8: true,
+            utf8_checked: 0,
+        }
+    }
+
+    // Text messages are checked as their fragments arrive unless disabled
+    pub fn validate_utf8(mut self, validate: bool) -> Self {
+        self.validate_utf8 = validate;
+        self
+    }
+
+    // Returns the opcode and payload once a message is complete. Control
+    // frames are returned as they come, even in the middle of a fragmented message.
+    pub fn push(&mut self, mut frame: Frame) -> Result<Option<(Opcode, Vec<u8>)>> {
+        let payload: Vec<u8> = frame.payload_data.take().unwrap_or_default();
+
+        match frame.opcode {
+            Opcode::Text | Opcode::Binary => {
+                if self.opcode.is_some() {
+                    return Err(Error::Protocol(String::from(
+                        "expected a continuation frame",
+                    )));
+                }
+
+                if payload.len() > self.max_message_size {
+                    return Err(Error::PayloadTooLarge {
+                        size: payload.len(),
+                        max_size: self.max_message_size,
+                    });
+                }
+
+                let mut checked: usize = 0;
+                if self.validate_utf8 && frame.opcode == Opcode::Text {
+                    Self::check_utf8(&payload, &mut checked, frame.is_final)?;
+                }
+
+                if frame.is_final {
+                    Ok(Some((frame.opcode, payload)))
+                } else {
+                    self.opcode = Some(frame.opcode);
+                    self.data = payload;
+                    self.utf8_checked = checked;
+                    Ok(None)
+                }
+            }
+            Opcode::Continuation => {
+                if self.opcode.is_none() {
+                    return Err(Error::Protocol(String::from(
+                        "unexpected continuation frame",
+                    )));
+                }
+
+                self.data.extend_from_slice(&payload);
+                if self.data.len() > self.max_message_size {
+                    return Err(Error::PayloadTooLarge {
+                        size: self.data.len(),
+                        max_size: self.max_message_size,
+                    });
+                }
+
+                if self.validate_utf8 && self.opcode == Some(Opcode::Text) {
+                    Self::check_utf8(&self.data, &mut self.utf8_checked, frame.is_final)?;
+                }
+
+                if frame.is_final {
+                    let opcode: Opcode = self.opcode.take().unwrap();
+                    Ok(Some((opcode, std::mem::take(&mut self.data))))
+                } else {
+                    Ok(None)
+                }
+            }
+            Opcode::Close | Opcode::Ping | Opcode::Pong => Ok(Some((frame.opcode, payload))),
+            Opcode::Unknow => Err(Error::Protocol(String::from("unknown opcode"))),
+        }
+    }
+
+    // Only the bytes after `checked` are validated. A character cut at the end
+    // of a fragment is completed by the next one, so it is only an error once
+    // the message is final.
+    fn check_utf8(data: &[u8], checked: &mut usize, is_final: bool) -> Result<()> {
+        match std::str::from_utf8(&data[*checked..]) {
+            Ok(_) => {
+                *checked = data.len();
+                Ok(())
+            }
+            Err(err) if err.error_len().is_none() && !is_final => {
+                *checked += err.valid_up_to();
+                Ok(())
+            }
+            Err(err) => Err(Error::Utf8(err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{server::Message, websocket_types::CloseCode};
+
+    use super::*;
+
+    fn frame(opcode: Opcode, is_final: bool, payload: &[u8]) -> Frame {
+        Frame {
+            is_final,
+            opcode,
+            payload_data: Some(payload.to_vec()),
+            ..Frame::default()
+        }
+    }
+
+    #[test]
+    fn character_split_across_fragments() {
+        let mut assembler: MessageAssembler = MessageAssembler::new(1024);
+        let text: &[u8] = "héllo €".as_bytes();
+
+        // Both characters are cut between two fragments
+        assert!(assembler
+            .push(frame(Opcode::Text, false, &text[..2]))
+            .unwrap()
+            .is_none());
+        assert!(assembler
+            .push(frame(Opcode::Continuation, false, &text[2..8]))
+            .unwrap()
+            .is_none());
+        let message = assembler.push(frame(Opcode::Continuation, true, &text[8..]));
+        assert_eq!(message.unwrap(), Some((Opcode::Text, text.to_vec())));
+    }
+
+    #[test]
+    fn invalid_byte_fails_before_the_message_ends() {
+        let mut assembler: MessageAssembler = MessageAssembler::new(1024);
+        let result = assembler.push(frame(Opcode::Text, false, b"ab\xffcd"));
+        assert!(matches!(result, Err(Error::Utf8(_))));
+
+        let mut assembler: MessageAssembler = MessageAssembler::new(1024);
+        assembler.push(frame(Opcode::Text, false, b"ab")).unwrap();
+        let result = assembler.push(frame(Opcode::Continuation, false, b"\xc3\x28"));
+        assert!(matches!(result, Err(Error::Utf8(_))));
+    }
+
+    #[test]
+    fn truncated_final_fragment() {
+        let mut assembler: MessageAssembler = MessageAssembler::new(1024);
+        assembler.push(frame(Opcode::Text, false, b"ab")).unwrap();
+        let err: Error = assembler
+            .push(frame(Opcode::Continuation, true, b"\xe2\x82"))
+            .unwrap_err();
+        assert!(matches!(err, Error::Utf8(_)));
+        assert_eq!(err.close_code(), CloseCode::InvalidPayload);
+        assert_eq!(err.close_code().as_u16(), 1007);
+
+        let mut assembler: MessageAssembler = MessageAssembler::new(1024);
+        let result = assembler.push(frame(Opcode::Text, true, b"\xe2\x82"));
+        assert!(matches!(result, Err(Error::Utf8(_))));
+    }
+
+    #[test]
+    fn lossy_replacement_without_validation() {
+        let mut assembler: MessageAssembler = MessageAssembler::new(1024).validate_utf8(false);
+        assembler
+            .push(frame(Opcode::Text, false, b"ab\xff"))
+            .unwrap();
+        let (opcode, data) = assembler
+            .push(frame(Opcode::Continuation, true, b"cd\xe2\x82"))
+            .unwrap()
+            .unwrap();
+
+        let message: Message = Message::from_opcode(opcode, data.into());
+        assert_eq!(message.as_text(), Some("ab\u{fffd}cd\u{fffd}"));
+    }
+
+    #[test]
+    fn binary_is_not_checked() {
+        let mut assembler: MessageAssembler = MessageAssembler::new(1024);
+        let message = assembler.push(frame(Opcode::Binary, true, b"\xff"));
+        assert_eq!(message.unwrap(), Some((Opcode::Binary, vec![0xff])));
+    }
+}
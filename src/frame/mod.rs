@@ -1 +1,3 @@
+pub mod frame_decoder;
 pub mod frame_types;
+pub mod message_assembler;
@@ -1,24 +1,38 @@
-use std::error::Error;
-
 use crate::{
+    error::{Error, Result},
     utils::generate_accept,
     websocket_types::{RequestStruct, ResponseStruct},
 };
 
-pub fn create_response(request: RequestStruct) -> Result<ResponseStruct, Box<dyn Error>> {
+pub fn create_response(request: RequestStruct) -> Result<ResponseStruct> {
     let mut response: ResponseStruct = ResponseStruct::default();
 
-    if let Some(key) = request.headers.get("key").cloned() {
-        response
-            .headers
-            .insert(String::from("Sec-WebSocket-Accept"), generate_accept(key));
-        response
-            .headers
-            .insert(String::from("Connection"), String::from("Upgrade"));
-        response
-            .headers
-            .insert(String::from("Upgrade"), String::from("websocket"));
+    match request.header("Sec-WebSocket-Key").cloned() {
+        Some(key) => {
+            response
+                .headers
+                .insert(String::from("Sec-WebSocket-Accept"), generate_accept(key));
+        }
+        None => {
+            return Err(Error::Handshake(String::from(
+                "missing Sec-WebSocket-Key header",
+            )))
+        }
     }
 
     Ok(response)
 }
+
+// The first protocol in the server's preference order that the client offered
+pub fn select_protocol(request: &RequestStruct, supported: &[String]) -> Option<String> {
+    let offered: Vec<&str> = request
+        .header("Sec-WebSocket-Protocol")?
+        .split(',')
+        .map(str::trim)
+        .collect();
+
+    supported
+        .iter()
+        .find(|protocol| offered.contains(&protocol.as_str()))
+        .cloned()
+}
@@ -1,5 +1,5 @@
 mod create_message;
 mod parse_message;
 
-pub use create_message::create_response;
-pub use parse_message::parse_request;
+pub use create_message::{create_response, select_protocol};
+pub use parse_message::{parse_request, parse_response};
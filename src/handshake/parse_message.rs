@@ -1,7 +1,11 @@
-use httparse::{Header, Request, Status};
-use std::{collections::HashMap, error::Error, io::ErrorKind};
+use httparse::{Header, Request, Response, Status};
+use std::collections::HashMap;
 
-use crate::websocket_types::RequestStruct;
+use crate::{
+    error::{Error, Result},
+    http_types::HttpStatus,
+    websocket_types::{RequestStruct, ResponseStruct},
+};
 
 fn convert_headers(headers: &[Header<'_>]) -> HashMap<String, String> {
     let mut hashmap: HashMap<String, String> = HashMap::new();
@@ -14,9 +18,9 @@ fn convert_headers(headers: &[Header<'_>]) -> HashMap<String, String> {
     hashmap
 }
 
-pub fn parse_request(data: String) -> Result<RequestStruct, Box<dyn Error>> {
+pub fn parse_request(data: String, max_headers: usize) -> Result<RequestStruct> {
     let mut request_struct: RequestStruct = RequestStruct::new();
-    let mut headers = [httparse::EMPTY_HEADER; 16];
+    let mut headers = vec![httparse::EMPTY_HEADER; max_headers];
     let mut request = Request::new(&mut headers);
 
     match request.parse(data.as_ref()) {
@@ -26,13 +30,28 @@ pub fn parse_request(data: String) -> Result<RequestStruct, Box<dyn Error>> {
             request_struct.headers = convert_headers(request.headers);
             Ok(request_struct)
         }
-        Ok(Status::Partial) => Err(Box::new(std::io::Error::new(
-            ErrorKind::InvalidData,
-            "invalid_request",
-        ))),
-        Err(_) => Err(Box::new(std::io::Error::new(
-            ErrorKind::UnexpectedEof,
-            "unexpected",
-        ))),
+        Ok(Status::Partial) => Err(Error::Handshake(String::from("incomplete request"))),
+        Err(httparse::Error::TooManyHeaders) => Err(Error::HeadersTooLarge),
+        Err(err) => Err(Error::Handshake(err.to_string())),
+    }
+}
+
+pub fn parse_response(data: &[u8], max_headers: usize) -> Result<(ResponseStruct, usize)> {
+    let mut headers = vec![httparse::EMPTY_HEADER; max_headers];
+    let mut response = Response::new(&mut headers);
+
+    match response.parse(data) {
+        Ok(Status::Complete(len)) => {
+            let code: u16 = response.code.unwrap();
+            let status: HttpStatus = HttpStatus::from_code(code)
+                .ok_or_else(|| Error::Handshake(format!("unexpected status {}", code)))?;
+            let response_struct: ResponseStruct = ResponseStruct {
+                status,
+                headers: convert_headers(response.headers),
+            };
+            Ok((response_struct, len))
+        }
+        Ok(Status::Partial) => Err(Error::Handshake(String::from("incomplete response"))),
+        Err(err) => Err(Error::Handshake(err.to_string())),
     }
 }
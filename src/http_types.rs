@@ -1,15 +1,34 @@
 pub const HTTP_VERSION: &str = "HTTP/1.1";
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum HttpStatus {
     SwitchingProtocols,
     BadRequest,
     Unauthorized,
     Forbidden,
     NotFound,
+    RequestTimeout,
+    RequestHeaderFieldsTooLarge,
     InternalServerError,
+    ServiceUnavailable,
 }
 
 impl HttpStatus {
+    pub fn from_code(code: u16) -> Option<Self> {
+        match code {
+            101 => Some(HttpStatus::SwitchingProtocols),
+            400 => Some(HttpStatus::BadRequest),
+            401 => Some(HttpStatus::Unauthorized),
+            403 => Some(HttpStatus::Forbidden),
+            404 => Some(HttpStatus::NotFound),
+            408 => Some(HttpStatus::RequestTimeout),
+            431 => Some(HttpStatus::RequestHeaderFieldsTooLarge),
+            500 => Some(HttpStatus::InternalServerError),
+            503 => Some(HttpStatus::ServiceUnavailable),
+            _ => None,
+        }
+    }
+
     pub fn as_str(&self) -> String {
         let status_code = match self {
             HttpStatus::SwitchingProtocols => "101 Switching Protocols",
@@ -17,7 +36,10 @@ impl HttpStatus {
             HttpStatus::Unauthorized => "401 Unauthorized",
             HttpStatus::Forbidden => "403 Forbidden",
             HttpStatus::NotFound => "404 Not Found",
+            HttpStatus::RequestTimeout => "408 Request Timeout",
+            HttpStatus::RequestHeaderFieldsTooLarge => "431 Request Header Fields Too Large",
             HttpStatus::InternalServerError => "500 Internal Server Error",
+            HttpStatus::ServiceUnavailable => "503 Service Unavailable",
         };
         let status = format!("{} {}", HTTP_VERSION, status_code);
         status
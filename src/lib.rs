@@ -1,8 +1,12 @@
-mod frame;
+pub mod client;
+mod error;
+pub mod extension;
+pub mod frame;
 mod handshake;
 mod http_types;
 pub mod server;
 mod utils;
 mod websocket_types;
+pub use error::{Error, Result};
 pub use http_types::HttpStatus;
-pub use websocket_types::RequestStruct;
+pub use websocket_types::{CloseCode, CloseFrame, RequestStruct};
@@ -0,0 +1,59 @@
+use std::{any::Any, collections::HashMap, sync::Arc};
+
+use crate::{http_types::HttpStatus, websocket_types::RequestStruct};
+
+#[derive(Debug, Clone)]
+pub struct Rejection {
+    pub status: HttpStatus,
+    pub headers: HashMap<String, String>,
+}
+
+impl Rejection {
+    pub fn new(status: HttpStatus) -> Self {
+        Self {
+            status,
+            headers: HashMap::new(),
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.insert(name.to_string(), value.to_string());
+        self
+    }
+}
+
+// Runs before routing and `on_join`, the identity it returns is then
+// available through `Public::identity` in `on_join` and `on_message`.
+// `on_close` and `on_error` get no `Public`, handlers that need it there
+// copy it into their `State` in `on_join`.
+#[async_trait::async_trait]
+pub trait Authenticator: Send + Sync {
+    type Identity: Send + Sync + 'static;
+
+    async fn authenticate(
+        &self,
+        request: &RequestStruct,
+    ) -> std::result::Result<Self::Identity, Rejection>;
+}
+
+pub(crate) type Identity = Arc<dyn Any + Send + Sync>;
+
+// Object safe version of `Authenticator` stored by the server
+#[async_trait::async_trait]
+pub(crate) trait AnyAuthenticator: Send + Sync {
+    async fn authenticate(
+        &self,
+        request: &RequestStruct,
+    ) -> std::result::Result<Identity, Rejection>;
+}
+
+#[async_trait::async_trait]
+impl<A: Authenticator> AnyAuthenticator for A {
+    async fn authenticate(
+        &self,
+        request: &RequestStruct,
+    ) -> std::result::Result<Identity, Rejection> {
+        let identity: A::Identity = Authenticator::authenticate(self, request).await?;
+        Ok(Arc::new(identity))
+    }
+}
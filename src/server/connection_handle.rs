@@ -0,0 +1,259 @@
+use std::{
+    io::{self, IoSlice},
+    sync::Arc,
+};
+
+use bytes::Bytes;
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        watch, OwnedSemaphorePermit,
+    },
+};
+
+use crate::{
+    error::{Error, Result},
+    extension::Extensions,
+    frame::frame_types::{Frame, Opcode, PayloadLen},
+    websocket_types::{CloseCode, CloseFrame},
+};
+
+use super::Message;
+
+// Messages are framed by the writer task, after the connection's extensions
+// had a chance to transform them
+pub(crate) enum Outgoing {
+    Message(Opcode, Bytes),
+    Raw(Vec<u8>),
+    // Given back once everything queued before it was written
+    Release(OwnedSemaphorePermit),
+    Shutdown,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConnectionHandle {
+    sender: UnboundedSender<Outgoing>,
+    closing: Arc<watch::Sender<bool>>,
+}
+
+impl ConnectionHandle {
+    pub(crate) fn new() -> (Self, UnboundedReceiver<Outgoing>) {
+        let (sender, receiver) = unbounded_channel();
+        let (closing, _) = watch::channel(false);
+
+        let handle: ConnectionHandle = Self {
+            sender,
+            closing: Arc::new(closing),
+        };
+        (handle, receiver)
+    }
+
+    pub fn send<M: Into<Message>>(&self, message: M) -> Result<()> {
+        match message.into() {
+            Message::Frame(frame) => {
+                if self.is_closing() {
+                    return Err(Error::Closed);
+                }
+                self.send_bytes(frame.to_bytes())
+            }
+            Message::Close(Some(close_frame)) => {
+                close_frame.check_code()?;
+                self.send_frame(Opcode::Close, Bytes::from(close_frame.to_bytes()))
+            }
+            message => match message.into_opcode() {
+                Some((opcode, payload)) => self.send_frame(opcode, payload),
+                None => Ok(()),
+            },
+        }
+    }
+
+    // The permit is held until the message reached the socket, which lets
+    // writers wait for the queue to drain
+    pub(crate) fn send_reserved(
+        &self,
+        message: Message,
+        permit: OwnedSemaphorePermit,
+    ) -> Result<()> {
+        self.send(message)?;
+        let _ = self.sender.send(Outgoing::Release(permit));
+        Ok(())
+    }
+
+    pub fn send_string(&self, message: String) -> Result<()> {
+        self.send_frame(Opcode::Text, Bytes::from(message))
+    }
+
+    pub fn send_binary(&self, data: Vec<u8>) -> Result<()> {
+        self.send_frame(Opcode::Binary, Bytes::from(data))
+    }
+
+    pub fn close(&self, code: CloseCode, reason: &str) -> Result<()> {
+        self.send(Message::Close(Some(CloseFrame::new(code, reason))))
+    }
+
+    pub fn is_connected(&self) -> bool {
+        !self.sender.is_closed() && !self.is_closing()
+    }
+
+    pub(crate) fn send_frame(&self, opcode: Opcode, payload: Bytes) -> Result<()> {
+        if opcode == Opcode::Close {
+            // Only the first Close frame may be sent
+            let mut already_closing: bool = false;
+            self.closing.send_if_modified(|closing| {
+                already_closing = *closing;
+                *closing = true;
+                !already_closing
+            });
+            if already_closing {
+                return Err(Error::Closed);
+            }
+        } else if self.is_closing() {
+            return Err(Error::Closed);
+        }
+
+        self.sender
+            .send(Outgoing::Message(opcode, payload))
+            .map_err(|_| Error::Closed)
+    }
+
+    pub(crate) fn send_bytes(&self, data: Vec<u8>) -> Result<()> {
+        self.sender
+            .send(Outgoing::Raw(data))
+            .map_err(|_| Error::Closed)
+    }
+
+    pub(crate) fn is_closing(&self) -> bool {
+        *self.closing.borrow()
+    }
+
+    pub(crate) fn closing(&self) -> watch::Receiver<bool> {
+        self.closing.subscribe()
+    }
+
+    pub(crate) fn shutdown(&self) {
+        let _ = self.sender.send(Outgoing::Shutdown);
+    }
+}
+
+fn encode_frame(extensions: &Extensions, opcode: Opcode, payload: Bytes) -> Result<Frame> {
+    // Extensions transform an owned payload, a shared one is copied here
+    let payload: Vec<u8> = Vec::from(payload);
+    let mut frame: Frame = Frame {
+        opcode,
+        payload_length: PayloadLen::from_size(payload.len()),
+        payload_data: Some(payload),
+        ..Frame::default()
+    };
+    extensions.encode(&mut frame)?;
+    Ok(frame)
+}
+
+pub(crate) async fn write_outgoing<W: AsyncWrite + Unpin>(
+    mut writer: W,
+    mut receiver: UnboundedReceiver<Outgoing>,
+    extensions: Extensions,
+) {
+    while let Some(outgoing) = receiver.recv().await {
+        let written: io::Result<()> = match outgoing {
+            // The payload is written from the shared buffer, broadcasts are not copied
+            Outgoing::Message(opcode, payload) if extensions.is_empty() => {
+                let frame: Frame = Frame {
+                    opcode,
+                    ..Frame::default()
+                };
+                write_frame(&mut writer, &frame.header_bytes(payload.len()), &payload).await
+            }
+            Outgoing::Message(opcode, payload) => {
+                let frame: Frame = match encode_frame(&extensions, opcode, payload) {
+                    Ok(frame) => frame,
+                    Err(_) => break,
+                };
+                let payload: &[u8] = frame.payload_data.as_deref().unwrap_or_default();
+                write_frame(&mut writer, &frame.header_bytes(payload.len()), payload).await
+            }
+            Outgoing::Raw(data) => writer.write_all(&data).await,
+            Outgoing::Release(permit) => {
+                drop(permit);
+                Ok(())
+            }
+            Outgoing::Shutdown => break,
+        };
+
+        if written.is_err() {
+            break;
+        }
+    }
+    let _ = writer.shutdown().await;
+}
+
+// Header and payload are given to the socket together when it supports it
+async fn write_frame<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    header: &[u8],
+    payload: &[u8],
+) -> io::Result<()> {
+    let mut written: usize = 0;
+    while written < header.len() + payload.len() {
+        let count: usize = if written < header.len() {
+            let slices: [IoSlice<'_>; 2] =
+                [IoSlice::new(&header[written..]), IoSlice::new(payload)];
+            writer.write_vectored(&slices).await?
+        } else {
+            writer.write(&payload[written - header.len()..]).await?
+        };
+
+        if count == 0 {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        written += count;
+    }
+    Ok(())
+}
+
+// Before the upgrade only the HTTP response may reach the peer, frames queued
+// by on_join or a broadcast are dropped
+pub(crate) async fn write_rejection<W: AsyncWrite + Unpin>(
+    mut writer: W,
+    mut receiver: UnboundedReceiver<Outgoing>,
+) {
+    while let Some(outgoing) = receiver.recv().await {
+        match outgoing {
+            Outgoing::Raw(data) => {
+                if writer.write_all(&data).await.is_err() {
+                    break;
+                }
+            }
+            Outgoing::Message(..) | Outgoing::Release(_) => {}
+            Outgoing::Shutdown => break,
+        }
+    }
+    let _ = writer.shutdown().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{duplex, AsyncReadExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn shared_payloads_are_framed() {
+        let (handle, receiver) = ConnectionHandle::new();
+        let (mut client, socket) = duplex(256);
+        tokio::spawn(write_outgoing(socket, receiver, Extensions::default()));
+
+        let long: Bytes = Bytes::from(vec![7; 70_000]);
+        handle.send(Message::binary(long.clone())).unwrap();
+        handle.send("short").unwrap();
+        handle.shutdown();
+
+        let mut written: Vec<u8> = Vec::new();
+        client.read_to_end(&mut written).await.unwrap();
+
+        let mut expected: Vec<u8> = vec![0x82, 127, 0, 0, 0, 0, 0, 1, 0x11, 0x70];
+        expected.extend_from_slice(&long);
+        expected.extend_from_slice(b"\x81\x05short");
+        assert_eq!(written, expected);
+    }
+}
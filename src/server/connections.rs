@@ -0,0 +1,143 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+};
+
+use super::{ConnectionHandle, Message};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ConnectionId(u64);
+
+impl fmt::Display for ConnectionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    handles: HashMap<ConnectionId, ConnectionHandle>,
+    rooms: HashMap<String, HashSet<ConnectionId>>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Connections {
+    registry: Arc<Mutex<Registry>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl Connections {
+    pub fn get(&self, id: ConnectionId) -> Option<ConnectionHandle> {
+        self.lock().handles.get(&id).cloned()
+    }
+
+    pub fn ids(&self) -> Vec<ConnectionId> {
+        self.lock().handles.keys().copied().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().handles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().handles.is_empty()
+    }
+
+    pub fn broadcast(&self, message: Message) {
+        let handles: Vec<ConnectionHandle> = self.lock().handles.values().cloned().collect();
+        Self::send_all(handles, message);
+    }
+
+    pub fn broadcast_except(&self, except: ConnectionId, message: Message) {
+        let handles: Vec<ConnectionHandle> = self
+            .lock()
+            .handles
+            .iter()
+            .filter(|(id, _)| **id != except)
+            .map(|(_, handle)| handle.clone())
+            .collect();
+        Self::send_all(handles, message);
+    }
+
+    pub fn join_room(&self, id: ConnectionId, room: &str) {
+        let mut registry: MutexGuard<'_, Registry> = self.lock();
+        if registry.handles.contains_key(&id) {
+            registry
+                .rooms
+                .entry(room.to_string())
+                .or_default()
+                .insert(id);
+        }
+    }
+
+    pub fn leave_room(&self, id: ConnectionId, room: &str) {
+        let mut registry: MutexGuard<'_, Registry> = self.lock();
+        if let Some(members) = registry.rooms.get_mut(room) {
+            members.remove(&id);
+            if members.is_empty() {
+                registry.rooms.remove(room);
+            }
+        }
+    }
+
+    pub fn room_members(&self, room: &str) -> Vec<ConnectionId> {
+        self.lock()
+            .rooms
+            .get(room)
+            .map(|members| members.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn send_to_room(&self, room: &str, message: Message) {
+        let handles: Vec<ConnectionHandle> = {
+            let registry: MutexGuard<'_, Registry> = self.lock();
+            match registry.rooms.get(room) {
+                Some(members) => members
+                    .iter()
+                    .filter_map(|id| registry.handles.get(id).cloned())
+                    .collect(),
+                None => return,
+            }
+        };
+        Self::send_all(handles, message);
+    }
+
+    pub(crate) fn next_id(&self) -> ConnectionId {
+        ConnectionId(self.next_id.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub(crate) fn insert(&self, id: ConnectionId, handle: ConnectionHandle) {
+        self.lock().handles.insert(id, handle);
+    }
+
+    pub(crate) fn remove(&self, id: ConnectionId) {
+        let mut registry: MutexGuard<'_, Registry> = self.lock();
+        registry.handles.remove(&id);
+        registry.rooms.retain(|_, members| {
+            members.remove(&id);
+            !members.is_empty()
+        });
+    }
+
+    pub(crate) fn drain(&self) -> Vec<ConnectionHandle> {
+        let mut registry: MutexGuard<'_, Registry> = self.lock();
+        registry.rooms.clear();
+        registry.handles.drain().map(|(_, handle)| handle).collect()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Registry> {
+        self.registry.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    // The payload is shared by every recipient instead of being copied, unless
+    // an extension such as compression has to transform it for a connection
+    fn send_all(handles: Vec<ConnectionHandle>, message: Message) {
+        for handle in handles {
+            let _ = handle.send(message.clone());
+        }
+    }
+}
@@ -1,10 +1,66 @@
-use crate::websocket_types::RequestStruct;
+use std::{any::Any, sync::Arc};
+
+use crate::{
+    error::Error,
+    websocket_types::{CloseFrame, RequestStruct},
+};
 
 use super::Public;
 
+// `State` is created by `on_join` and owned by the connection task until `on_close`
 #[async_trait::async_trait]
 pub trait EventHandler: Send + Sync {
-    async fn on_join(&self, request: RequestStruct, public: &mut Public);
-    async fn on_message(&self, public: &mut Public);
-    async fn on_close(&self);
+    type State: Send + 'static;
+
+    async fn on_join(&self, request: RequestStruct, public: &mut Public) -> Self::State;
+    async fn on_message(&self, state: &mut Self::State, public: &mut Public);
+    async fn on_close(&self, state: &mut Self::State, close_frame: Option<CloseFrame>);
+
+    // Called when the peer breaks the protocol, right before the connection is
+    // closed with the matching code and `on_close` runs
+    async fn on_error(&self, _state: &mut Self::State, _error: Error) {}
+}
+
+pub(crate) type State = Box<dyn Any + Send>;
+pub(crate) type Handler = Arc<dyn AnyHandler>;
+
+// Handlers with different `State` types are stored side by side by the router
+#[async_trait::async_trait]
+pub(crate) trait AnyHandler: Send + Sync {
+    async fn on_join(&self, request: RequestStruct, public: &mut Public) -> State;
+    async fn on_message(&self, state: &mut State, public: &mut Public);
+    async fn on_close(&self, state: &mut State, close_frame: Option<CloseFrame>);
+    async fn on_error(&self, state: &mut State, error: Error);
+}
+
+struct Erased<H: ?Sized>(Box<H>);
+
+#[async_trait::async_trait]
+impl<H: EventHandler + ?Sized + 'static> AnyHandler for Erased<H> {
+    async fn on_join(&self, request: RequestStruct, public: &mut Public) -> State {
+        Box::new(self.0.on_join(request, public).await)
+    }
+
+    async fn on_message(&self, state: &mut State, public: &mut Public) {
+        // The state always comes from this handler's `on_join`
+        if let Some(state) = state.downcast_mut::<H::State>() {
+            self.0.on_message(state, public).await;
+        }
+    }
+
+    async fn on_close(&self, state: &mut State, close_frame: Option<CloseFrame>) {
+        if let Some(state) = state.downcast_mut::<H::State>() {
+            self.0.on_close(state, close_frame).await;
+        }
+    }
+
+    async fn on_error(&self, state: &mut State, error: Error) {
+        if let Some(state) = state.downcast_mut::<H::State>() {
+            self.0.on_error(state, error).await;
+        }
+    }
+}
+
+pub(crate) fn erase_handler<H: EventHandler + ?Sized + 'static>(handler: Box<H>) -> Handler {
+    Arc::new(Erased(handler))
 }
@@ -0,0 +1,148 @@
+use std::io;
+#[cfg(unix)]
+use std::{
+    fs::{DirBuilder, Permissions},
+    os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream},
+};
+
+// Source of incoming connections for `Server::run`, anything that yields a
+// byte stream can be served (TCP, Unix sockets, in-memory pipes, ...)
+#[async_trait::async_trait]
+pub trait Listener: Send + Sync + 'static {
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+
+    async fn accept(&self) -> io::Result<Self::Stream>;
+}
+
+#[async_trait::async_trait]
+impl Listener for TcpListener {
+    type Stream = TcpStream;
+
+    async fn accept(&self) -> io::Result<Self::Stream> {
+        let (socket, _) = TcpListener::accept(self).await?;
+        Ok(socket)
+    }
+}
+
+#[cfg(unix)]
+#[async_trait::async_trait]
+impl Listener for UnixListener {
+    type Stream = UnixStream;
+
+    async fn accept(&self) -> io::Result<Self::Stream> {
+        let (socket, _) = UnixListener::accept(self).await?;
+        Ok(socket)
+    }
+}
+
+#[cfg(unix)]
+pub(crate) async fn bind_unix(path: &Path, mode: Option<u32>) -> io::Result<UnixListener> {
+    // A socket file left behind by a previous process makes bind fail, it is
+    // only removed when nothing is listening on it anymore
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ));
+        }
+        match UnixStream::connect(path).await {
+            Ok(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("{} is already in use", path.display()),
+                ));
+            }
+            Err(_) => std::fs::remove_file(path)?,
+        }
+    }
+
+    match mode {
+        Some(mode) => bind_with_mode(path, mode),
+        None => UnixListener::bind(path),
+    }
+}
+
+// The socket is bound in a directory only the owner can enter and moved into
+// place once it has its final mode, so nobody can connect to it before
+#[cfg(unix)]
+fn bind_with_mode(path: &Path, mode: u32) -> io::Result<UnixListener> {
+    let file_name = path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a file path", path.display()),
+        )
+    })?;
+    let parent: &Path = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    let private: PathBuf = parent.join(format!(".ws-{:08x}", rand::random::<u32>()));
+    DirBuilder::new().mode(0o700).create(&private)?;
+    let bound: PathBuf = private.join(file_name);
+
+    let listener: io::Result<UnixListener> = UnixListener::bind(&bound).and_then(|listener| {
+        std::fs::set_permissions(&bound, Permissions::from_mode(mode))?;
+        std::fs::rename(&bound, path)?;
+        Ok(listener)
+    });
+    let _ = std::fs::remove_file(&bound);
+    let _ = std::fs::remove_dir(&private);
+    listener
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn socket_path(name: &str) -> PathBuf {
+        let directory: PathBuf =
+            std::env::temp_dir().join(format!("webchaussette-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir(&directory).unwrap();
+        directory.join("ws.sock")
+    }
+
+    #[tokio::test]
+    async fn stale_socket_is_replaced_with_the_mode() {
+        let path: PathBuf = socket_path("stale");
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        assert!(UnixStream::connect(&path).await.is_err());
+
+        let listener: UnixListener = bind_unix(&path, Some(0o640)).await.unwrap();
+        let mode: u32 = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+
+        // Nothing is left behind next to the socket
+        let entries: usize = std::fs::read_dir(path.parent().unwrap()).unwrap().count();
+        assert_eq!(entries, 1);
+
+        let _client: UnixStream = UnixStream::connect(&path).await.unwrap();
+        assert!(Listener::accept(&listener).await.is_ok());
+
+        // A socket still in use is left alone
+        let err: io::Error = bind_unix(&path, None).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn other_files_are_not_removed() {
+        let path: PathBuf = socket_path("file");
+        std::fs::write(&path, b"data").unwrap();
+
+        let err: io::Error = bind_unix(&path, Some(0o600)).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read(&path).unwrap(), b"data");
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
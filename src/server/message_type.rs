@@ -1,22 +1,194 @@
-use crate::frame::frame_types::Opcode;
+use std::{fmt, ops::Deref, str::Utf8Error};
 
-#[derive(Debug)]
-pub enum Types {
-    String(String),
-    Binary(Vec<u8>),
-    None,
+use bytes::Bytes;
+
+use crate::{
+    frame::frame_types::{Frame, Opcode},
+    websocket_types::CloseFrame,
+};
+
+// Payloads are reference counted, cloning a message to send it to several
+// connections does not copy it
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Text(Utf8Bytes),
+    Binary(Bytes),
+    Ping(Bytes),
+    Pong(Bytes),
+    Close(Option<CloseFrame>),
+    // Written as is without going through extensions, never produced when reading
+    Frame(Frame),
 }
 
-impl Types {
-    pub fn from_opcode(opcode: Opcode, data: Vec<u8>) -> Self {
+impl Message {
+    pub fn text<T: Into<Utf8Bytes>>(text: T) -> Self {
+        Self::Text(text.into())
+    }
+
+    pub fn binary<B: Into<Bytes>>(data: B) -> Self {
+        Self::Binary(data.into())
+    }
+
+    pub fn is_text(&self) -> bool {
+        matches!(self, Self::Text(_))
+    }
+
+    pub fn is_binary(&self) -> bool {
+        matches!(self, Self::Binary(_))
+    }
+
+    pub fn is_close(&self) -> bool {
+        matches!(self, Self::Close(_))
+    }
+
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            Self::Text(text) => Some(text.as_str()),
+            _ => None,
+        }
+    }
+
+    pub fn into_data(self) -> Bytes {
+        match self {
+            Self::Text(text) => text.into(),
+            Self::Binary(data) | Self::Ping(data) | Self::Pong(data) => data,
+            Self::Close(close_frame) => close_frame
+                .map(|close_frame| Bytes::from(close_frame.to_bytes()))
+                .unwrap_or_default(),
+            Self::Frame(frame) => frame.payload_data.map(Bytes::from).unwrap_or_default(),
+        }
+    }
+
+    // Invalid UTF-8 in a Text payload is replaced
+    pub(crate) fn from_opcode(opcode: Opcode, data: Bytes) -> Self {
         match opcode {
-            Opcode::Text => Self::String(String::from_utf8_lossy(&data).to_string()),
-            Opcode::Binary => Self::Binary(data),
+            Opcode::Text => match Utf8Bytes::try_from(data.clone()) {
+                Ok(text) => Self::Text(text),
+                Err(_) => Self::Text(String::from_utf8_lossy(&data).into_owned().into()),
+            },
+            Opcode::Ping => Self::Ping(data),
+            Opcode::Pong => Self::Pong(data),
+            Opcode::Close => Self::Close(CloseFrame::from_bytes(&data).ok().flatten()),
+            _ => Self::Binary(data),
+        }
+    }
 
-            // TODO
-            _ => {
-                unimplemented!()
-            }
+    // Raw frames have no opcode and payload to be framed again
+    pub(crate) fn into_opcode(self) -> Option<(Opcode, Bytes)> {
+        match self {
+            Self::Text(text) => Some((Opcode::Text, text.into())),
+            Self::Binary(data) => Some((Opcode::Binary, data)),
+            Self::Ping(data) => Some((Opcode::Ping, data)),
+            Self::Pong(data) => Some((Opcode::Pong, data)),
+            Self::Close(close_frame) => Some((
+                Opcode::Close,
+                close_frame
+                    .map(|close_frame| Bytes::from(close_frame.to_bytes()))
+                    .unwrap_or_default(),
+            )),
+            Self::Frame(_) => None,
         }
     }
 }
+
+impl From<String> for Message {
+    fn from(text: String) -> Self {
+        Self::text(text)
+    }
+}
+
+impl From<&str> for Message {
+    fn from(text: &str) -> Self {
+        Self::text(text)
+    }
+}
+
+impl From<Vec<u8>> for Message {
+    fn from(data: Vec<u8>) -> Self {
+        Self::binary(data)
+    }
+}
+
+impl From<Bytes> for Message {
+    fn from(data: Bytes) -> Self {
+        Self::binary(data)
+    }
+}
+
+// Bytes that are always valid UTF-8
+#[derive(Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Utf8Bytes(Bytes);
+
+impl Utf8Bytes {
+    pub const fn from_static(text: &'static str) -> Self {
+        Self(Bytes::from_static(text.as_bytes()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        // SAFETY: every constructor checks or guarantees the content is UTF-8
+        unsafe { std::str::from_utf8_unchecked(&self.0) }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Deref for Utf8Bytes {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Debug for Utf8Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for Utf8Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<String> for Utf8Bytes {
+    fn from(text: String) -> Self {
+        Self(Bytes::from(text))
+    }
+}
+
+impl From<&str> for Utf8Bytes {
+    fn from(text: &str) -> Self {
+        Self(Bytes::copy_from_slice(text.as_bytes()))
+    }
+}
+
+impl TryFrom<Bytes> for Utf8Bytes {
+    type Error = Utf8Error;
+
+    fn try_from(data: Bytes) -> Result<Self, Utf8Error> {
+        std::str::from_utf8(&data)?;
+        Ok(Self(data))
+    }
+}
+
+impl From<Utf8Bytes> for Bytes {
+    fn from(text: Utf8Bytes) -> Self {
+        text.0
+    }
+}
+
+impl PartialEq<str> for Utf8Bytes {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Utf8Bytes {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
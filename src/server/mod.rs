@@ -1,8 +1,29 @@
+#[allow(clippy::module_inception)]
 mod server;
 mod event_handler;
 mod public_handler;
 mod message_type;
+mod connection_handle;
+mod connections;
+mod server_builder;
+mod listener;
+mod router;
+mod origin_policy;
+mod authenticator;
+mod websocket;
+#[cfg(feature = "tls")]
+mod tls;
 pub use server::*;
 pub use event_handler::*;
 pub use public_handler::*;
-pub use message_type::*;
\ No newline at end of file
+pub use message_type::*;
+pub use connection_handle::*;
+pub use connections::*;
+pub use server_builder::*;
+pub use listener::*;
+pub use router::*;
+pub use origin_policy::*;
+pub use authenticator::*;
+pub use websocket::*;
+#[cfg(feature = "tls")]
+pub use tls::*;
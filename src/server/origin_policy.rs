@@ -0,0 +1,208 @@
+use std::{fmt, sync::Arc};
+
+#[cfg(feature = "regex")]
+use regex::Regex;
+
+#[derive(Clone)]
+enum OriginRule {
+    Exact(String),
+    // "https://*.example.com" is split around the wildcard
+    Subdomain {
+        prefix: String,
+        suffix: String,
+    },
+    #[cfg(feature = "regex")]
+    Regex(Regex),
+    Custom(Arc<dyn Fn(&str) -> bool + Send + Sync>),
+}
+
+impl OriginRule {
+    fn matches(&self, origin: &str) -> bool {
+        match self {
+            OriginRule::Exact(allowed) => *allowed == origin,
+            OriginRule::Subdomain { prefix, suffix } => {
+                origin.len() > prefix.len() + suffix.len()
+                    && origin.starts_with(prefix.as_str())
+                    && origin.ends_with(suffix.as_str())
+                    && !origin[prefix.len()..origin.len() - suffix.len()].contains(['/', ':'])
+            }
+            #[cfg(feature = "regex")]
+            OriginRule::Regex(regex) => regex.is_match(origin),
+            OriginRule::Custom(check) => check(origin),
+        }
+    }
+}
+
+// Browsers always send an Origin header with the upgrade request, so an
+// allow-list keeps other web pages from opening sockets with the user's cookies
+#[derive(Clone)]
+pub struct OriginPolicy {
+    rules: Vec<OriginRule>,
+    allow_missing: bool,
+}
+
+impl OriginPolicy {
+    pub fn new() -> Self {
+        Self {
+            rules: Vec::new(),
+            allow_missing: true,
+        }
+    }
+
+    // Either an exact origin or one with a subdomain wildcard, e.g. "https://*.example.com"
+    pub fn allow(mut self, origin: &str) -> Self {
+        let origin: String = Self::normalize(origin);
+        let rule: OriginRule = match origin.split_once("*.") {
+            Some((prefix, suffix)) => OriginRule::Subdomain {
+                prefix: prefix.to_string(),
+                suffix: format!(".{}", suffix),
+            },
+            None => OriginRule::Exact(origin),
+        };
+        self.rules.push(rule);
+        self
+    }
+
+    #[cfg(feature = "regex")]
+    pub fn allow_regex(mut self, regex: Regex) -> Self {
+        self.rules.push(OriginRule::Regex(regex));
+        self
+    }
+
+    pub fn allow_fn<F>(mut self, check: F) -> Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.rules.push(OriginRule::Custom(Arc::new(check)));
+        self
+    }
+
+    // Non-browser clients usually do not send an Origin header
+    pub fn allow_missing(mut self, allow: bool) -> Self {
+        self.allow_missing = allow;
+        self
+    }
+
+    pub fn is_allowed(&self, origin: Option<&str>) -> bool {
+        match origin {
+            Some(origin) => {
+                let origin: String = Self::normalize(origin);
+                self.rules.iter().any(|rule| rule.matches(&origin))
+            }
+            None => self.allow_missing,
+        }
+    }
+
+    // Default ports are dropped, as browsers do when they serialize an origin
+    fn normalize(origin: &str) -> String {
+        let origin: String = origin.trim().trim_end_matches('/').to_ascii_lowercase();
+        let default_port: Option<&str> = match origin.split_once("://") {
+            Some(("https" | "wss", _)) => Some(":443"),
+            Some(("http" | "ws", _)) => Some(":80"),
+            _ => None,
+        };
+
+        match default_port.and_then(|port| origin.strip_suffix(port)) {
+            Some(origin) => origin.to_string(),
+            None => origin,
+        }
+    }
+}
+
+impl Default for OriginPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for OriginPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OriginPolicy")
+            .field("rules", &self.rules.len())
+            .field("allow_missing", &self.allow_missing)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> OriginPolicy {
+        OriginPolicy::new()
+            .allow("https://app.example.com")
+            .allow("https://*.example.com")
+            .allow("http://localhost:3000")
+    }
+
+    #[test]
+    fn exact_origins() {
+        let policy: OriginPolicy = policy();
+        assert!(policy.is_allowed(Some("https://app.example.com")));
+        assert!(policy.is_allowed(Some("http://localhost:3000")));
+        assert!(!policy.is_allowed(Some("http://localhost:3001")));
+        assert!(!policy.is_allowed(Some("http://localhost")));
+        assert!(!policy.is_allowed(Some("null")));
+    }
+
+    #[test]
+    fn subdomain_wildcard() {
+        let policy: OriginPolicy = policy();
+        assert!(policy.is_allowed(Some("https://chat.example.com")));
+        assert!(policy.is_allowed(Some("https://a.b.example.com")));
+
+        assert!(!policy.is_allowed(Some("https://example.com")));
+        assert!(!policy.is_allowed(Some("https://evil.com")));
+        assert!(!policy.is_allowed(Some("https://example.com.evil.com")));
+        assert!(!policy.is_allowed(Some("https://evilexample.com")));
+        assert!(!policy.is_allowed(Some("https://evil.com/.example.com")));
+        assert!(!policy.is_allowed(Some("https://a.example.com:8443")));
+    }
+
+    #[test]
+    fn scheme_must_match() {
+        let policy: OriginPolicy = policy();
+        assert!(!policy.is_allowed(Some("http://app.example.com")));
+        assert!(!policy.is_allowed(Some("http://chat.example.com")));
+        assert!(!policy.is_allowed(Some("https://localhost:3000")));
+    }
+
+    #[test]
+    fn origins_are_normalized() {
+        let policy: OriginPolicy = OriginPolicy::new()
+            .allow("HTTPS://App.Example.com/")
+            .allow("https://*.Example.com:443");
+        assert!(policy.is_allowed(Some("https://app.example.com")));
+        assert!(policy.is_allowed(Some("https://APP.example.com/")));
+        assert!(policy.is_allowed(Some(" https://app.example.com ")));
+
+        // Default ports name the same origin
+        assert!(policy.is_allowed(Some("https://app.example.com:443")));
+        assert!(policy.is_allowed(Some("https://a.example.com:443")));
+        assert!(!policy.is_allowed(Some("https://app.example.com:80")));
+    }
+
+    #[test]
+    fn missing_origin() {
+        assert!(policy().is_allowed(None));
+        assert!(!policy().allow_missing(false).is_allowed(None));
+        assert!(!OriginPolicy::new().is_allowed(Some("https://app.example.com")));
+    }
+
+    #[test]
+    fn custom_check() {
+        let policy: OriginPolicy =
+            OriginPolicy::new().allow_fn(|origin| origin.ends_with(".internal"));
+        assert!(policy.is_allowed(Some("http://api.internal")));
+        assert!(!policy.is_allowed(Some("http://api.internal.evil.com")));
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn regex_rule() {
+        let regex: Regex = Regex::new(r"^https://pr-\d+\.preview\.dev$").unwrap();
+        let policy: OriginPolicy = OriginPolicy::new().allow_regex(regex);
+        assert!(policy.is_allowed(Some("https://pr-42.preview.dev")));
+        assert!(!policy.is_allowed(Some("https://pr-42.preview.dev.evil.com")));
+    }
+}
@@ -1,25 +1,40 @@
-use std::{collections::HashMap, error::Error};
-
-use tokio::{io::AsyncWriteExt, net::TcpStream};
+use std::{any::Any, collections::HashMap};
 
 use crate::{
-    frame::frame_types::{Frame, Opcode, PayloadLen},
+    error::Result,
     http_types::HttpStatus,
     utils::build_response,
-    websocket_types::ResponseStruct,
+    websocket_types::{CloseCode, ResponseStruct},
 };
 
-use super::Types;
+use super::{ConnectionHandle, ConnectionId, Connections, Identity, Message};
 
 #[derive(Debug)]
-pub struct Public<'a> {
-    pub socket: &'a mut TcpStream,
-    pub message: Types,
+pub struct Public {
+    pub id: ConnectionId,
+    pub connections: Connections,
+    pub handle: ConnectionHandle,
+    pub params: HashMap<String, String>,
+    pub protocol: Option<String>,
+    pub(crate) identity: Option<Identity>,
+    // None in on_join
+    pub message: Option<Message>,
     pub closed: bool,
+    // False while on_join runs, before the upgrade response is sent
+    pub(crate) upgraded: bool,
 }
 
-impl<'a> Public<'a> {
-    pub async fn close_handshake(&mut self, status: HttpStatus) -> Result<(), Box<dyn Error>> {
+impl Public {
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(String::as_str)
+    }
+
+    // The identity returned by the server's `Authenticator`, if its type is `T`
+    pub fn identity<T: Any>(&self) -> Option<&T> {
+        self.identity.as_deref()?.downcast_ref::<T>()
+    }
+
+    pub async fn close_handshake(&mut self, status: HttpStatus) -> Result<()> {
         let response_struct: ResponseStruct = ResponseStruct {
             status,
             headers: HashMap::new(),
@@ -29,39 +44,32 @@ impl<'a> Public<'a> {
 
         self.closed = true;
 
-        match self.socket.write(response.as_bytes()).await {
-            Ok(_) => Ok(()),
-            Err(err) => Err(Box::new(err)),
-        }
+        self.handle.send_bytes(response.into_bytes())
     }
 
-    pub async fn send_string(&mut self, message: String) -> Result<(), Box<dyn Error>> {
-        let message_vec: Vec<u8> = message.as_bytes().to_vec();
-
-        let mut frame: Frame = Frame::default();
-        frame.opcode = Opcode::Text;
-        frame.payload_length = PayloadLen::from_size(message_vec.len());
-        frame.payload_data = Some(message_vec);
+    pub async fn send<M: Into<Message>>(&mut self, message: M) -> Result<()> {
+        self.handle.send(message)
+    }
 
-        match self.socket.write(&frame.to_bytes()).await {
-            Ok(_) => Ok(()),
-            Err(err) => Err(Box::new(err)),
-        }
+    pub async fn send_string(&mut self, message: String) -> Result<()> {
+        self.handle.send_string(message)
     }
 
     pub async fn close(&mut self) {
         self.closed = true;
     }
 
-    pub async fn send_binary(&mut self, data: Vec<u8>) -> Result<(), Box<dyn Error>> {
-        let mut frame: Frame = Frame::default();
-        frame.opcode = Opcode::Binary;
-        frame.payload_length = PayloadLen::from_size(data.len());
-        frame.payload_data = Some(data);
-
-        match self.socket.write(&frame.to_bytes()).await {
-            Ok(_) => Ok(()),
-            Err(err) => Err(Box::new(err)),
+    // In on_join the client is still waiting for an HTTP response, so it is
+    // refused with 403 instead of receiving a Close frame
+    pub async fn close_with(&mut self, code: CloseCode, reason: &str) -> Result<()> {
+        if !self.upgraded {
+            return self.close_handshake(HttpStatus::Forbidden).await;
         }
+        self.closed = true;
+        self.handle.close(code, reason)
+    }
+
+    pub async fn send_binary(&mut self, data: Vec<u8>) -> Result<()> {
+        self.handle.send_binary(data)
     }
 }
@@ -0,0 +1,243 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::utils::percent_decode_path;
+
+use super::{erase_handler, EventHandler, Handler};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Static(String),
+    Param(String),
+    Wildcard(String),
+}
+
+struct Route {
+    segments: Vec<Segment>,
+    handler: Handler,
+}
+
+// Patterns are matched segment by segment in insertion order:
+// "/rooms/:id" captures `id`, "/files/*path" captures the rest of the path.
+// Captured values are percent-decoded.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    fallback: Option<Handler>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn route<H>(mut self, pattern: &str, handler: Box<H>) -> Self
+    where
+        H: EventHandler + ?Sized + 'static,
+    {
+        self.routes.push(Route {
+            segments: Self::parse_pattern(pattern),
+            handler: erase_handler(handler),
+        });
+        self
+    }
+
+    // Handles requests that match no route instead of answering 404
+    pub fn fallback<H>(mut self, handler: Box<H>) -> Self
+    where
+        H: EventHandler + ?Sized + 'static,
+    {
+        self.fallback = Some(erase_handler(handler));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty() && self.fallback.is_none()
+    }
+
+    pub(crate) fn find(&self, uri: &str) -> Option<(Handler, HashMap<String, String>)> {
+        let path: &str = uri.split(['?', '#']).next().unwrap_or_default();
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+        for route in &self.routes {
+            if let Some(params) = Self::matches(&route.segments, &segments) {
+                return Some((Arc::clone(&route.handler), params));
+            }
+        }
+
+        self.fallback
+            .as_ref()
+            .map(|handler| (Arc::clone(handler), HashMap::new()))
+    }
+
+    fn parse_pattern(pattern: &str) -> Vec<Segment> {
+        pattern
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(|segment| {
+                if let Some(name) = segment.strip_prefix(':') {
+                    Segment::Param(name.to_string())
+                } else if let Some(name) = segment.strip_prefix('*') {
+                    Segment::Wildcard(name.to_string())
+                } else {
+                    Segment::Static(segment.to_string())
+                }
+            })
+            .collect()
+    }
+
+    fn matches(pattern: &[Segment], path: &[&str]) -> Option<HashMap<String, String>> {
+        let mut params: HashMap<String, String> = HashMap::new();
+
+        for (i, segment) in pattern.iter().enumerate() {
+            match segment {
+                // A wildcard swallows everything left, including nothing
+                Segment::Wildcard(name) => {
+                    if !name.is_empty() {
+                        let rest: String = path.get(i..)?.join("/");
+                        params.insert(name.clone(), percent_decode_path(&rest));
+                    }
+                    return Some(params);
+                }
+                Segment::Param(name) => {
+                    params.insert(name.clone(), percent_decode_path(path.get(i)?));
+                }
+                Segment::Static(value) => {
+                    if path.get(i) != Some(&value.as_str()) {
+                        return None;
+                    }
+                }
+            }
+        }
+
+        (pattern.len() == path.len()).then_some(params)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::{
+        server::Public,
+        websocket_types::{CloseFrame, RequestStruct},
+    };
+
+    struct Test;
+
+    #[async_trait]
+    impl EventHandler for Test {
+        type State = ();
+
+        async fn on_join(&self, _request: RequestStruct, _public: &mut Public) {}
+
+        async fn on_message(&self, _state: &mut (), _public: &mut Public) {}
+
+        async fn on_close(&self, _state: &mut (), _close_frame: Option<CloseFrame>) {}
+    }
+
+    fn router() -> Router {
+        Router::new()
+            .route("/rooms/:id", Box::new(Test))
+            .route("/rooms/:id/users/:user", Box::new(Test))
+            .route("/files/*path", Box::new(Test))
+            .route("/health", Box::new(Test))
+    }
+
+    // Index of the matched route along with its params
+    fn matched(router: &Router, uri: &str) -> Option<(usize, HashMap<String, String>)> {
+        let (handler, params) = router.find(uri)?;
+        let index: usize = router
+            .routes
+            .iter()
+            .position(|route| Arc::ptr_eq(&route.handler, &handler))?;
+        Some((index, params))
+    }
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn params_are_captured() {
+        let router: Router = router();
+        assert_eq!(
+            matched(&router, "/rooms/42"),
+            Some((0, params(&[("id", "42")])))
+        );
+        assert_eq!(
+            matched(&router, "/rooms/42/users/ana"),
+            Some((1, params(&[("id", "42"), ("user", "ana")])))
+        );
+        assert_eq!(matched(&router, "/rooms"), None);
+        assert_eq!(matched(&router, "/rooms/42/users"), None);
+    }
+
+    #[test]
+    fn wildcard_takes_the_rest() {
+        let router: Router = router();
+        assert_eq!(
+            matched(&router, "/files/a/b/c.txt"),
+            Some((2, params(&[("path", "a/b/c.txt")])))
+        );
+        assert_eq!(
+            matched(&router, "/files"),
+            Some((2, params(&[("path", "")])))
+        );
+        assert_eq!(
+            matched(&router, "/files/"),
+            Some((2, params(&[("path", "")])))
+        );
+    }
+
+    #[test]
+    fn static_segments_and_normalization() {
+        let router: Router = router();
+        assert_eq!(matched(&router, "/health"), Some((3, HashMap::new())));
+        assert_eq!(matched(&router, "/health/"), Some((3, HashMap::new())));
+        assert_eq!(matched(&router, "/Health"), None);
+        assert_eq!(matched(&router, "/healthz"), None);
+        assert_eq!(matched(&router, "/"), None);
+
+        // Query strings and fragments are not part of the path
+        assert_eq!(
+            matched(&router, "/rooms/42?token=abc#top"),
+            Some((0, params(&[("id", "42")])))
+        );
+        assert_eq!(
+            matched(&router, "/health?rooms/1"),
+            Some((3, HashMap::new()))
+        );
+    }
+
+    #[test]
+    fn params_are_percent_decoded() {
+        let router: Router = router();
+        assert_eq!(
+            matched(&router, "/rooms/caf%C3%A9%20room+1"),
+            Some((0, params(&[("id", "café room+1")])))
+        );
+        assert_eq!(
+            matched(&router, "/files/my%20docs/a%2Fb"),
+            Some((2, params(&[("path", "my docs/a/b")])))
+        );
+    }
+
+    #[test]
+    fn fallback_or_not_found() {
+        assert!(router().find("/unknown").is_none());
+        assert!(Router::new().find("/").is_none());
+        assert!(Router::new().is_empty());
+
+        let fallback: Router = router().fallback(Box::new(Test));
+        let (handler, params) = fallback.find("/unknown").unwrap();
+        assert!(Arc::ptr_eq(&handler, fallback.fallback.as_ref().unwrap()));
+        assert!(params.is_empty());
+
+        // Routes still win over the fallback
+        assert_eq!(matched(&fallback, "/health"), Some((3, HashMap::new())));
+    }
+}
@@ -1,176 +1,901 @@
 use crate::{
-    frame::frame_types::{Frame, Opcode, PayloadLen},
-    handshake::{create_response, parse_request},
-    utils::{build_response, generate_accept},
-    websocket_types::{ResponseStruct, BUFFER_SIZE},
+    error::{Error, Result},
+    extension::{ExtensionOffer, Extensions},
+    frame::{
+        frame_decoder::FrameDecoder,
+        frame_types::{Frame, Opcode},
+        message_assembler::MessageAssembler,
+    },
+    handshake::{create_response, parse_request, select_protocol},
+    http_types::HttpStatus,
+    utils::build_response,
+    websocket_types::{CloseCode, CloseFrame, ResponseStruct, CLOSE_TIMEOUT, MAX_HANDSHAKE_SIZE},
     RequestStruct,
 };
-use std::sync::Arc;
+use std::{collections::HashMap, future::Future, pin::Pin, sync::Arc, time::Duration};
 
+use bytes::Bytes;
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::{TcpListener, TcpStream},
-    sync::{Mutex, MutexGuard},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpListener,
+    sync::{mpsc::unbounded_channel, watch, OwnedSemaphorePermit, Semaphore},
+    task::JoinSet,
+    time::{interval_at, sleep, timeout, Instant, Interval, Sleep},
 };
 
-use super::{EventHandler, Public, Types};
+use super::{
+    write_outgoing, write_rejection, ConnectionHandle, ConnectionId, Connections, EventHandler,
+    Forward, Handler, Identity, Incoming, Listener, Message, Public, Rejection, Router,
+    ServerBuilder, ServerConfig, State,
+};
+
+// What the router picked for a connection during the handshake
+struct Session {
+    handler: Option<Handler>,
+    params: HashMap<String, String>,
+    protocol: Option<String>,
+    identity: Option<Identity>,
+    state: Option<State>,
+    extensions: Extensions,
+}
 
-pub struct Server {
-    listener: TcpListener,
-    event_listener: Option<Box<dyn EventHandler + Send>>,
+pub struct Server<L: Listener = TcpListener> {
+    listener: L,
+    router: Router,
+    connections: Connections,
+    config: ServerConfig,
 }
 
 impl Server {
-    pub async fn new(url: &str) -> Self {
-        let listener: TcpListener = TcpListener::bind(url).await.unwrap();
+    pub async fn new(url: &str) -> Result<Self> {
+        ServerBuilder::new(url).build().await
+    }
+
+    pub fn builder(url: &str) -> ServerBuilder {
+        ServerBuilder::new(url)
+    }
+
+    pub(crate) async fn bind(url: &str, config: ServerConfig, router: Router) -> Result<Self> {
+        let listener: TcpListener = TcpListener::bind(url).await?;
+        Ok(Self::with_config(listener, config, router))
+    }
+}
+
+impl<L: Listener> Server<L> {
+    pub fn with_listener(listener: L) -> Self {
+        Self::with_config(listener, ServerConfig::default(), Router::new())
+    }
+
+    pub(crate) fn with_config(listener: L, config: ServerConfig, router: Router) -> Self {
         Self {
             listener,
-            event_listener: None,
-        }
-    }
-
-    async fn handshake(&self, socket: Arc<Mutex<TcpStream>>) -> bool {
-        let mut buffer: [u8; 1024] = [0; 1024];
-        let mut response: String = String::new();
-        let mut socket: MutexGuard<'_, TcpStream> = socket.lock().await;
-        let mut request: RequestStruct = RequestStruct::new();
-
-        match socket.read(&mut buffer).await {
-            Ok(n) => {
-                let mut data: Vec<u8> = buffer.to_vec();
-                data.resize(n, 0);
-                match parse_request(String::from_utf8_lossy(&data).to_string()) {
-                    Ok(parsed) => {
-                        request = parsed.clone();
-                        let mut response_struct: ResponseStruct =
-                            create_response(parsed.clone()).unwrap();
-                        response_struct.headers.insert(
-                            String::from("Sec-WebSocket-Accept"),
-                            generate_accept(
-                                parsed.headers.get("Sec-WebSocket-Key").unwrap().clone(),
-                            ),
-                        );
-                        let response_builded: String = build_response(response_struct);
-                        response = response_builded;
-                    }
-                    // TODO
-                    Err(err) => println!("{:?}", err),
+            router,
+            connections: Connections::default(),
+            config,
+        }
+    }
+
+    async fn handshake<R, W>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+        id: ConnectionId,
+        handle: &ConnectionHandle,
+        upgraded: Option<RequestStruct>,
+    ) -> Option<Session>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        // Without a response to send, the upgrade was already answered by another server
+        let (request, mut response): (RequestStruct, Option<ResponseStruct>) = match upgraded {
+            Some(request) => (request, None),
+            None => {
+                let (request, response) = self.read_handshake(reader, handle).await?;
+                (request, Some(response))
+            }
+        };
+        let upgraded: bool = response.is_none();
+
+        if let Some(policy) = &self.config.origin_policy {
+            if !policy.is_allowed(request.header("Origin").map(String::as_str)) {
+                Self::reject_with(handle, Rejection::new(HttpStatus::Forbidden), upgraded);
+                return None;
+            }
+        }
+
+        let identity: Option<Identity> = match &self.config.authenticator {
+            Some(authenticator) => match authenticator.authenticate(&request).await {
+                Ok(identity) => Some(identity),
+                Err(rejection) => {
+                    Self::reject_with(handle, rejection, upgraded);
+                    return None;
                 }
+            },
+            None => None,
+        };
+
+        // An upgraded connection got the same subprotocol from the other server
+        // as long as both support the same ones
+        let protocol: Option<String> = match &response {
+            Some(response) => response.header("Sec-WebSocket-Protocol").cloned(),
+            None => select_protocol(&request, &self.config.subprotocols),
+        };
+        let extensions: Extensions = match &mut response {
+            Some(response) => self.negotiate_extensions(&request, response),
+            None => Extensions::default(),
+        };
+
+        // Without any handler every path is accepted
+        let mut session: Session = match self.router.find(&request.uri) {
+            Some((handler, params)) => Session {
+                handler: Some(handler),
+                params,
+                protocol,
+                identity,
+                state: None,
+                extensions,
+            },
+            None if self.router.is_empty() => Session {
+                handler: None,
+                params: HashMap::new(),
+                protocol,
+                identity,
+                state: None,
+                extensions,
+            },
+            None => {
+                Self::reject_with(handle, Rejection::new(HttpStatus::NotFound), upgraded);
+                return None;
             }
-            Err(_) => {
-                return false;
+        };
+
+        // Only connections that passed every check can be reached by broadcasts,
+        // on_join may already use rooms
+        self.connections.insert(id, handle.clone());
+
+        if let Some(event) = &session.handler {
+            let mut public: Public = self.public(id, handle, &session, None);
+            public.upgraded = upgraded;
+
+            session.state = Some(event.on_join(request, &mut public).await);
+
+            if public.closed {
+                return None;
             }
         }
-        if self.event_listener.is_some() {
-            let mut public: Public = Public {
-                socket: &mut socket,
-                message: Types::None,
-                closed: false,
+
+        // Frames queued by on_join are only flushed after the upgrade response
+        let response: String = match response {
+            Some(response) => build_response(response),
+            None => return Some(session),
+        };
+        match writer.write_all(response.as_bytes()).await {
+            Ok(()) => Some(session),
+            Err(_) => None,
+        }
+    }
+
+    async fn read_handshake<R: AsyncRead + Unpin>(
+        &self,
+        reader: &mut R,
+        handle: &ConnectionHandle,
+    ) -> Option<(RequestStruct, ResponseStruct)> {
+        let accepted: Result<(RequestStruct, ResponseStruct)> =
+            match timeout(self.config.handshake_timeout, self.read_request(reader)).await {
+                Ok(accepted) => accepted,
+                Err(_) => {
+                    Self::reject(handle, HttpStatus::RequestTimeout);
+                    return None;
+                }
             };
 
-            let event: &Box<dyn EventHandler + Send> = self.event_listener.as_ref().unwrap();
-            event.on_join(request, &mut public).await;
+        match accepted {
+            Ok(accepted) => Some(accepted),
+            Err(Error::Io(_) | Error::Closed) => None,
+            Err(err) => {
+                Self::reject(handle, err.http_status());
+                None
+            }
+        }
+    }
 
-            if public.closed {
-                return false;
+    fn public(
+        &self,
+        id: ConnectionId,
+        handle: &ConnectionHandle,
+        session: &Session,
+        message: Option<Message>,
+    ) -> Public {
+        Public {
+            id,
+            connections: self.connections.clone(),
+            handle: handle.clone(),
+            params: session.params.clone(),
+            protocol: session.protocol.clone(),
+            identity: session.identity.clone(),
+            message,
+            closed: false,
+            upgraded: true,
+        }
+    }
+
+    async fn read_request<R: AsyncRead + Unpin>(
+        &self,
+        reader: &mut R,
+    ) -> Result<(RequestStruct, ResponseStruct)> {
+        let mut buffer: Vec<u8> = vec![0; self.config.read_buffer_size];
+        let mut data: Vec<u8> = Vec::new();
+
+        while !data.windows(4).any(|window| window == b"\r\n\r\n") {
+            if data.len() > MAX_HANDSHAKE_SIZE {
+                return Err(Error::HeadersTooLarge);
+            }
+
+            let n: usize = reader.read(&mut buffer).await?;
+            if n == 0 {
+                return Err(Error::Closed);
+            }
+            data.extend_from_slice(&buffer[..n]);
+        }
+
+        self.accept_request(&data)
+    }
+
+    fn reject(handle: &ConnectionHandle, status: HttpStatus) {
+        Self::reject_with(handle, Rejection::new(status), false);
+    }
+
+    // An upgraded connection can no longer receive an HTTP response
+    fn reject_with(handle: &ConnectionHandle, rejection: Rejection, upgraded: bool) {
+        if upgraded {
+            let _ = handle.close(CloseCode::PolicyViolation, &rejection.status.as_str());
+            return;
+        }
+
+        let response_struct: ResponseStruct = ResponseStruct {
+            status: rejection.status,
+            headers: rejection.headers,
+        };
+        let _ = handle.send_bytes(build_response(response_struct).into_bytes());
+    }
+
+    fn accept_request(&self, data: &[u8]) -> Result<(RequestStruct, ResponseStruct)> {
+        let request: RequestStruct = parse_request(
+            String::from_utf8_lossy(data).to_string(),
+            self.config.max_headers,
+        )?;
+        let mut response_struct: ResponseStruct = create_response(request.clone())?;
+
+        if !self.config.subprotocols.is_empty() {
+            match select_protocol(&request, &self.config.subprotocols) {
+                Some(protocol) => {
+                    response_struct
+                        .headers
+                        .insert(String::from("Sec-WebSocket-Protocol"), protocol);
+                }
+                None if self.config.require_subprotocol => {
+                    return Err(Error::Handshake(String::from("no supported subprotocol")));
+                }
+                None => {}
             }
         }
-        socket.write(response.as_bytes()).await.unwrap();
-        return true;
+
+        Ok((request, response_struct))
     }
 
-    async fn receive_data(&self, socket: Arc<Mutex<TcpStream>>) {
-        let mut buffer: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE];
-        let mut size: usize = 0;
-        let mut data_vec: Vec<u8> = Vec::new();
-        let mut frame: Frame = Frame::default();
-        let mut cur_size: usize = 0;
+    fn negotiate_extensions(
+        &self,
+        request: &RequestStruct,
+        response: &mut ResponseStruct,
+    ) -> Extensions {
+        let offers: Vec<ExtensionOffer> = request
+            .header("Sec-WebSocket-Extensions")
+            .map(|header| ExtensionOffer::parse(header))
+            .unwrap_or_default();
+        let (extensions, accepted): (Extensions, Vec<ExtensionOffer>) = Extensions::negotiate(
+            &self.config.extensions,
+            &offers,
+            self.config.max_message_size,
+        );
+
+        if !accepted.is_empty() {
+            let header: Vec<String> = accepted.iter().map(ExtensionOffer::to_string).collect();
+            response
+                .headers
+                .insert(String::from("Sec-WebSocket-Extensions"), header.join(", "));
+        }
+        extensions
+    }
 
-        let mut socket_guard: MutexGuard<'_, TcpStream> = socket.lock().await;
-        let socket: &mut TcpStream = &mut *socket_guard;
+    async fn receive_data<R: AsyncRead + Unpin>(
+        &self,
+        reader: &mut R,
+        id: ConnectionId,
+        handle: &ConnectionHandle,
+        session: &mut Session,
+    ) -> Option<CloseFrame> {
+        let mut buffer: Vec<u8> = vec![0; self.config.read_buffer_size];
+        let mut decoder: FrameDecoder = FrameDecoder::new(self.config.max_frame_size).masked(true);
+        let mut assembler: MessageAssembler = MessageAssembler::new(self.config.max_message_size)
+            .validate_utf8(self.config.validate_utf8);
+        let mut ping_timer: Option<Interval> = self
+            .config
+            .ping_interval
+            .map(|period| interval_at(Instant::now() + period, period));
+        let mut idle_timer: Option<Pin<Box<Sleep>>> = self
+            .config
+            .idle_timeout
+            .map(|idle_timeout| Box::pin(sleep(idle_timeout)));
+        let mut missed_pongs: u32 = 0;
+        let mut closing: watch::Receiver<bool> = handle.closing();
+
+        // Subscribing marks the current value as seen, so a close issued during
+        // the handshake would never wake `closing.changed()`
+        if handle.is_closing() {
+            return Self::wait_close(reader, &mut decoder, &mut buffer).await;
+        }
 
         loop {
-            match socket.read(&mut buffer).await {
-                Ok(n) if n > 0 => {
-                    let mut data: Vec<u8> = buffer.to_vec();
-                    data.resize(n, 0);
-
-                    if size == 0 {
-                        frame.default_header(data.clone());
-                        if frame.opcode == Opcode::Close {
-                            frame.payload_length = PayloadLen::LengthU8(0);
-                            let _ = socket.write(&frame.to_bytes()).await;
-                            return;
-                        }
-                        size = TryInto::<usize>::try_into(frame.payload_length.clone()).unwrap();
+            let read_result: std::io::Result<usize> = tokio::select! {
+                result = reader.read(&mut buffer) => result,
+                _ = Self::tick(&mut ping_timer) => {
+                    if missed_pongs >= self.config.max_missed_pongs {
+                        let _ = handle.close(CloseCode::GoingAway, "Ping timeout");
+                        return None;
                     }
-
-                    data_vec.append(&mut data);
-                    cur_size += n;
+                    missed_pongs += 1;
+                    let _ = handle.send_frame(Opcode::Ping, Bytes::new());
+                    continue;
                 }
-                Ok(_) => {
-                    break;
+                _ = Self::idle(&mut idle_timer) => {
+                    let _ = handle.close(CloseCode::GoingAway, "Idle timeout");
+                    return Self::wait_close(reader, &mut decoder, &mut buffer).await;
+                }
+                // The connection was closed through a handle
+                _ = closing.changed() => {
+                    return Self::wait_close(reader, &mut decoder, &mut buffer).await;
                 }
-                Err(_) => unimplemented!(),
+            };
+
+            if let (Some(idle_timer), Some(idle_timeout)) =
+                (&mut idle_timer, self.config.idle_timeout)
+            {
+                idle_timer.as_mut().reset(Instant::now() + idle_timeout);
             }
 
-            if cur_size >= size {
-                frame.default_from(data_vec.clone());
+            let frames: Vec<Frame> = match read_result {
+                Ok(n) if n > 0 => match decoder.feed(&buffer[..n]) {
+                    Ok(frames) => frames,
+                    Err(err) => {
+                        self.fail(handle, session, err).await;
+                        return None;
+                    }
+                },
+                _ => {
+                    break;
+                }
+            };
 
-                if self.event_listener.is_some() {
-                    let event: &Box<dyn EventHandler + Send> =
-                        self.event_listener.as_ref().unwrap();
-                    let mut public: Public = Public {
-                        socket,
-                        closed: false,
-                        message: Types::from_opcode(frame.opcode, frame.payload_data.unwrap()),
-                    };
-                    event.on_message(&mut public).await;
+            for mut frame in frames {
+                if let Err(err) = session.extensions.decode(&mut frame) {
+                    self.fail(handle, session, err).await;
+                    return None;
+                }
+
+                let (opcode, payload): (Opcode, Vec<u8>) = match assembler.push(frame) {
+                    Ok(Some(message)) => message,
+                    Ok(None) => continue,
+                    Err(err) => {
+                        self.fail(handle, session, err).await;
+                        return None;
+                    }
+                };
 
-                    if public.closed {
-                        return;
+                let payload: Bytes = Bytes::from(payload);
+                let message: Message = match opcode {
+                    Opcode::Close => match CloseFrame::from_bytes(&payload) {
+                        Ok(close_frame) => {
+                            // Echo the status code back to complete the closing handshake
+                            let _ = match &close_frame {
+                                Some(close_frame) => handle.close(close_frame.code, ""),
+                                None => handle.send_frame(Opcode::Close, Bytes::new()),
+                            };
+                            let message: Message = Message::Close(close_frame.clone());
+                            self.dispatch(id, handle, session, message).await;
+                            return close_frame;
+                        }
+                        Err(err) => {
+                            self.fail(handle, session, err).await;
+                            return None;
+                        }
+                    },
+                    Opcode::Ping => {
+                        let _ = handle.send_frame(Opcode::Pong, payload.clone());
+                        Message::Ping(payload)
+                    }
+                    Opcode::Pong => {
+                        missed_pongs = 0;
+                        Message::Pong(payload)
                     }
+                    _ => Message::from_opcode(opcode, payload),
+                };
+
+                let closed: bool = self.dispatch(id, handle, session, message).await;
+                if handle.is_closing() {
+                    return Self::wait_close(reader, &mut decoder, &mut buffer).await;
+                } else if closed {
+                    return None;
+                }
+            }
+        }
+        None
+    }
+
+    // Closes the connection because the peer broke the protocol, the handler
+    // sees the error before the Close frame is queued
+    async fn fail(&self, handle: &ConnectionHandle, session: &mut Session, err: Error) {
+        let (code, reason): (CloseCode, String) = (err.close_code(), err.to_string());
+        if let (Some(event), Some(state)) = (&session.handler, &mut session.state) {
+            event.on_error(state, err).await;
+        }
+        let _ = handle.close(code, &reason);
+    }
+
+    // Returns whether the handler closed the connection
+    async fn dispatch(
+        &self,
+        id: ConnectionId,
+        handle: &ConnectionHandle,
+        session: &mut Session,
+        message: Message,
+    ) -> bool {
+        let event: Handler = match session.handler.clone() {
+            Some(event) => event,
+            None => return false,
+        };
+
+        let mut public: Public = self.public(id, handle, session, Some(message));
+        if let Some(state) = &mut session.state {
+            event.on_message(state, &mut public).await;
+        }
+        public.closed
+    }
+
+    async fn wait_close<R: AsyncRead + Unpin>(
+        reader: &mut R,
+        decoder: &mut FrameDecoder,
+        buffer: &mut [u8],
+    ) -> Option<CloseFrame> {
+        let result = timeout(CLOSE_TIMEOUT, async {
+            loop {
+                let n: usize = match reader.read(buffer).await {
+                    Ok(n) if n > 0 => n,
+                    _ => return None,
+                };
+                let frames: Vec<Frame> = decoder.feed(&buffer[..n]).ok()?;
+
+                // Anything but the peer's Close frame is discarded at this point
+                if let Some(frame) = frames.into_iter().find(|f| f.opcode == Opcode::Close) {
+                    let payload: Vec<u8> = frame.payload_data.unwrap_or_default();
+                    return CloseFrame::from_bytes(&payload).ok().flatten();
                 }
+            }
+        })
+        .await;
+
+        result.ok().flatten()
+    }
+
+    async fn tick(timer: &mut Option<Interval>) {
+        match timer {
+            Some(timer) => {
+                timer.tick().await;
+            }
+            None => std::future::pending().await,
+        }
+    }
 
-                cur_size = 0;
-                size = 0;
-                data_vec.clear();
-                frame = Frame::default();
+    async fn idle(timer: &mut Option<Pin<Box<Sleep>>>) {
+        match timer {
+            Some(timer) => timer.as_mut().await,
+            None => std::future::pending().await,
+        }
+    }
+
+    async fn close(
+        &self,
+        id: ConnectionId,
+        handle: &ConnectionHandle,
+        session: &mut Session,
+        close_frame: Option<CloseFrame>,
+    ) {
+        self.connections.remove(id);
+        if let (Some(event), Some(state)) = (&session.handler, &mut session.state) {
+            event.on_close(state, close_frame).await;
+        }
+        handle.shutdown();
+    }
+
+    async fn accept(&self, socket: L::Stream, permit: Option<OwnedSemaphorePermit>) {
+        #[cfg(feature = "tls")]
+        if let Some(acceptor) = &self.config.tls {
+            // The TLS handshake shares the budget of the HTTP upgrade
+            if let Ok(Ok(stream)) =
+                timeout(self.config.handshake_timeout, acceptor.accept(socket)).await
+            {
+                self.serve(stream, permit, None).await;
             }
+            return;
         }
+
+        self.serve(socket, permit, None).await;
     }
 
-    async fn close(&self, socket: Arc<Mutex<TcpStream>>) {
-        if self.event_listener.is_some() {
-            let event: &Box<dyn EventHandler + Send> = self.event_listener.as_ref().unwrap();
-            event.on_close().await;
+    // Answers 503 once the connection limit is reached. Over TLS the response
+    // can only be sent after the TLS handshake.
+    async fn refuse(&self, socket: L::Stream) {
+        #[cfg(feature = "tls")]
+        if let Some(acceptor) = &self.config.tls {
+            if let Ok(Ok(stream)) =
+                timeout(self.config.handshake_timeout, acceptor.accept(socket)).await
+            {
+                Self::write_unavailable(stream).await;
+            }
+            return;
         }
-        let mut socket: MutexGuard<'_, TcpStream> = socket.lock().await;
-        socket.shutdown().await.expect("Close failed");
+
+        Self::write_unavailable(socket).await;
+    }
+
+    async fn write_unavailable<S: AsyncWrite + Unpin>(mut socket: S) {
+        let response_struct: ResponseStruct = ResponseStruct {
+            status: HttpStatus::ServiceUnavailable,
+            headers: HashMap::new(),
+        };
+        let response: String = build_response(response_struct);
+        let _ = socket.write_all(response.as_bytes()).await;
+        let _ = socket.shutdown().await;
+    }
+
+    // Serves a stream obtained outside of the listener, e.g. one end of a
+    // `tokio::io::duplex` pipe. The HTTP upgrade is read and answered as usual.
+    pub async fn serve_connection<S>(&self, stream: S)
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        self.serve(stream, None, None).await;
+    }
+
+    // Serves a stream already upgraded by another HTTP server such as hyper,
+    // which sent the 101 response itself. The request still goes through the
+    // origin policy, the authenticator and the router, and the subprotocol is
+    // picked from the configured ones. No extension is negotiated, and
+    // rejections close the connection with 1008.
+    pub async fn serve_upgraded<S>(&self, stream: S, request: RequestStruct)
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        self.serve(stream, None, Some(request)).await;
+    }
+
+    async fn serve<S>(
+        &self,
+        socket: S,
+        permit: Option<OwnedSemaphorePermit>,
+        upgraded: Option<RequestStruct>,
+    ) where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (mut reader, mut writer) = tokio::io::split(socket);
+        let (handle, receiver) = ConnectionHandle::new();
+        let id: ConnectionId = self.connections.next_id();
+        let already_upgraded: bool = upgraded.is_some();
+
+        let handshake = self.handshake(&mut reader, &mut writer, id, &handle, upgraded);
+        let mut session: Session = match handshake.await {
+            Some(session) => session,
+            None => {
+                self.connections.remove(id);
+                handle.shutdown();
+                // Flush a possible rejection, an HTTP response or a Close frame
+                // depending on whether the upgrade was already answered
+                match already_upgraded {
+                    true => write_outgoing(writer, receiver, Extensions::default()).await,
+                    false => write_rejection(writer, receiver).await,
+                }
+                return;
+            }
+        };
+        tokio::spawn(write_outgoing(writer, receiver, session.extensions.clone()));
+
+        let close_frame: Option<CloseFrame> = self
+            .receive_data(&mut reader, id, &handle, &mut session)
+            .await;
+        self.close(id, &handle, &mut session, close_frame).await;
+        drop(permit);
     }
 
     pub async fn run(self) {
+        self.run_until(std::future::pending::<()>()).await;
+    }
+
+    pub async fn run_until<F: Future>(self, shutdown: F) {
         let self_arc: Arc<Self> = Arc::new(self);
+        let mut tasks: JoinSet<()> = JoinSet::new();
+        let connection_limit: Option<Arc<Semaphore>> = self_arc
+            .config
+            .max_connections
+            .map(|count| Arc::new(Semaphore::new(count)));
+        tokio::pin!(shutdown);
 
         loop {
-            let (socket, _) = self_arc.listener.accept().await.unwrap();
-            let socket_arc: Arc<Mutex<TcpStream>> = Arc::new(Mutex::new(socket));
+            tokio::select! {
+                _ = &mut shutdown => break,
+                accepted = self_arc.listener.accept() => {
+                    let socket: L::Stream = match accepted {
+                        Ok(socket) => socket,
+                        Err(_) => continue,
+                    };
+
+                    let permit: Option<OwnedSemaphorePermit> = match &connection_limit {
+                        Some(limit) => match Arc::clone(limit).try_acquire_owned() {
+                            Ok(permit) => Some(permit),
+                            Err(_) => {
+                                let self_arc_clone: Arc<Self> = Arc::clone(&self_arc);
+                                tasks.spawn(async move {
+                                    self_arc_clone.refuse(socket).await;
+                                });
+                                continue;
+                            }
+                        },
+                        None => None,
+                    };
 
-            let self_arc_clone: Arc<Server> = Arc::clone(&self_arc);
-            let _ = tokio::spawn(async move {
-                if !self_arc_clone.clone().handshake(socket_arc.clone()).await {
-                    return;
+                    let self_arc_clone: Arc<Self> = Arc::clone(&self_arc);
+                    tasks.spawn(async move {
+                        self_arc_clone.accept(socket, permit).await;
+                    });
                 }
-                self_arc_clone
-                    .clone()
-                    .receive_data(socket_arc.clone())
-                    .await;
-                self_arc_clone.close(socket_arc).await;
-            });
+                // Reap finished connections so the set does not grow forever
+                Some(_) = tasks.join_next(), if !tasks.is_empty() => {}
+            }
+        }
+
+        self_arc
+            .connections
+            .broadcast(Message::Close(Some(CloseFrame::new(
+                CloseCode::GoingAway,
+                "Going Away",
+            ))));
+
+        let _ = timeout(self_arc.config.shutdown_grace_period, async {
+            while tasks.join_next().await.is_some() {}
+        })
+        .await;
+
+        // Connections still open after the grace period are dropped
+        tasks.shutdown().await;
+        for handle in self_arc.connections.drain() {
+            handle.shutdown();
+        }
+    }
+
+    // Serves connections in a background task and yields them as `WebSocket`s
+    // instead of dispatching them to handlers, which are replaced
+    pub fn incoming(self) -> Incoming {
+        self.incoming_until(std::future::pending::<()>())
+    }
+
+    // Same as `incoming`, the stream ends once the server shut down like `run_until`
+    pub fn incoming_until<F>(mut self, shutdown: F) -> Incoming
+    where
+        F: Future + Send + 'static,
+    {
+        let (sender, receiver) = unbounded_channel();
+        self.router = Router::new().fallback(Box::new(Forward::new(sender)));
+        Incoming::new(receiver, tokio::spawn(self.run_until(shutdown)))
+    }
+
+    pub fn connections(&self) -> Connections {
+        self.connections.clone()
+    }
+
+    pub fn set_handler<H>(&mut self, handler: Box<H>)
+    where
+        H: EventHandler + ?Sized + 'static,
+    {
+        self.router = std::mem::take(&mut self.router).fallback(handler);
+    }
+
+    pub fn set_router(&mut self, router: Router) {
+        self.router = router;
+    }
+
+    pub fn set_max_payload_size(&mut self, size: usize) {
+        self.config.max_frame_size = size;
+        self.config.max_message_size = size;
+    }
+
+    pub fn set_ping_interval(&mut self, interval: Duration) {
+        self.config.ping_interval = Some(interval);
+    }
+
+    pub fn set_max_missed_pongs(&mut self, count: u32) {
+        self.config.max_missed_pongs = count;
+    }
+
+    pub fn set_shutdown_grace_period(&mut self, grace_period: Duration) {
+        self.config.shutdown_grace_period = grace_period;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use tokio::{
+        io::{duplex, DuplexStream},
+        sync::oneshot,
+    };
+
+    struct Echo;
+
+    #[async_trait]
+    impl EventHandler for Echo {
+        type State = ();
+
+        async fn on_join(&self, _request: RequestStruct, _public: &mut Public) {}
+
+        async fn on_message(&self, _state: &mut (), public: &mut Public) {
+            if let Some(message @ Message::Text(_)) = public.message.take() {
+                let _ = public.send(message).await;
+            }
         }
+
+        async fn on_close(&self, _state: &mut (), _close_frame: Option<CloseFrame>) {}
+    }
+
+    // Records whether the connection was still open when on_error ran
+    struct Failing(std::sync::Mutex<Option<oneshot::Sender<bool>>>);
+
+    #[async_trait]
+    impl EventHandler for Failing {
+        type State = (ConnectionHandle, Option<oneshot::Sender<bool>>);
+
+        async fn on_join(&self, _request: RequestStruct, public: &mut Public) -> Self::State {
+            (public.handle.clone(), self.0.lock().unwrap().take())
+        }
+
+        async fn on_message(&self, _state: &mut Self::State, _public: &mut Public) {}
+
+        async fn on_close(&self, _state: &mut Self::State, _close_frame: Option<CloseFrame>) {}
+
+        async fn on_error(&self, state: &mut Self::State, _error: Error) {
+            if let Some(sender) = state.1.take() {
+                let _ = sender.send(state.0.is_connected());
+            }
+        }
+    }
+
+    async fn echo_server() -> Arc<Server> {
+        let mut server: Server = Server::new("127.0.0.1:0").await.unwrap();
+        server.set_handler(Box::new(Echo));
+        Arc::new(server)
     }
 
-    pub fn set_handler(&mut self, handler: Box<dyn EventHandler + Send>) {
-        self.event_listener = Some(handler);
+    const REQUEST: &str = "GET /chat HTTP/1.1\r\n\
+        Host: localhost\r\n\
+        Upgrade: websocket\r\n\
+        Connection: Upgrade\r\n\
+        Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+        Sec-WebSocket-Version: 13\r\n\r\n";
+
+    // Sends a masked text frame and expects it back unmasked
+    async fn assert_echo(client: &mut DuplexStream) {
+        let key: [u8; 4] = [1, 2, 3, 4];
+        let mut frame: Vec<u8> = vec![0x81, 0x80 | 5];
+        frame.extend_from_slice(&key);
+        frame.extend(
+            b"hello"
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| byte ^ key[i % 4]),
+        );
+        client.write_all(&frame).await.unwrap();
+
+        let mut echoed: [u8; 7] = [0; 7];
+        client.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(&echoed, b"\x81\x05hello");
+    }
+
+    #[tokio::test]
+    async fn serve_connection_answers_the_upgrade() {
+        let server: Arc<Server> = echo_server().await;
+        let (mut client, stream) = duplex(1024);
+        let served = tokio::spawn(async move { server.serve_connection(stream).await });
+
+        client.write_all(REQUEST.as_bytes()).await.unwrap();
+        let mut response: Vec<u8> = Vec::new();
+        while !response.ends_with(b"\r\n\r\n") {
+            response.push(client.read_u8().await.unwrap());
+        }
+        let response: String = String::from_utf8(response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 101"));
+        assert!(response.contains("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="));
+
+        assert_echo(&mut client).await;
+        drop(client);
+        served.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn serve_upgraded_skips_the_response() {
+        let server: Arc<Server> = echo_server().await;
+        let (mut client, stream) = duplex(1024);
+        let request: RequestStruct = parse_request(REQUEST.to_string(), 16).unwrap();
+        let served = tokio::spawn(async move { server.serve_upgraded(stream, request).await });
+
+        // The first bytes read are the echoed frame, not an HTTP response
+        assert_echo(&mut client).await;
+        drop(client);
+        served.await.unwrap();
+    }
+
+    // Replies with the subprotocol seen by on_join
+    struct Protocol;
+
+    #[async_trait]
+    impl EventHandler for Protocol {
+        type State = ();
+
+        async fn on_join(&self, _request: RequestStruct, public: &mut Public) {
+            let protocol: String = public.protocol.clone().unwrap_or_default();
+            let _ = public.send(protocol).await;
+        }
+
+        async fn on_message(&self, _state: &mut (), _public: &mut Public) {}
+
+        async fn on_close(&self, _state: &mut (), _close_frame: Option<CloseFrame>) {}
+    }
+
+    #[tokio::test]
+    async fn serve_upgraded_selects_the_subprotocol() {
+        let mut server: Server = Server::builder("127.0.0.1:0")
+            .subprotocols(["chat", "v2.chat"])
+            .build()
+            .await
+            .unwrap();
+        server.set_handler(Box::new(Protocol));
+        let (mut client, stream) = duplex(1024);
+        let request: String = REQUEST.replace(
+            "\r\n\r\n",
+            "\r\nSec-WebSocket-Protocol: v1.chat, v2.chat\r\n\r\n",
+        );
+        let request: RequestStruct = parse_request(request, 16).unwrap();
+        tokio::spawn(async move { server.serve_upgraded(stream, request).await });
+
+        let mut sent: [u8; 9] = [0; 9];
+        client.read_exact(&mut sent).await.unwrap();
+        assert_eq!(&sent, b"\x81\x07v2.chat");
+    }
+
+    #[tokio::test]
+    async fn on_error_runs_before_the_close_frame() {
+        let (sender, receiver) = oneshot::channel();
+        let mut server: Server = Server::new("127.0.0.1:0").await.unwrap();
+        server.set_handler(Box::new(Failing(std::sync::Mutex::new(Some(sender)))));
+        let (mut client, stream) = duplex(1024);
+        let request: RequestStruct = parse_request(REQUEST.to_string(), 16).unwrap();
+        tokio::spawn(async move { server.serve_upgraded(stream, request).await });
+
+        // Unmasked frames are a protocol error
+        client.write_all(b"\x81\x02hi").await.unwrap();
+        assert!(receiver.await.unwrap());
+
+        let mut close: [u8; 4] = [0; 4];
+        client.read_exact(&mut close).await.unwrap();
+        assert_eq!(close[0], 0x88);
+        assert_eq!(u16::from_be_bytes([close[2], close[3]]), 1002);
     }
 }
@@ -0,0 +1,230 @@
+#[cfg(unix)]
+use std::path::Path;
+use std::{sync::Arc, time::Duration};
+
+#[cfg(unix)]
+use tokio::net::UnixListener;
+
+#[cfg(feature = "deflate")]
+use crate::extension::DeflateConfig;
+use crate::{
+    error::Result,
+    extension::Extension,
+    websocket_types::{
+        BUFFER_SIZE, HANDSHAKE_TIMEOUT, MAX_HEADERS, MAX_MISSED_PONGS, MAX_PAYLOAD_SIZE,
+        SHUTDOWN_GRACE_PERIOD,
+    },
+};
+
+#[cfg(feature = "tls")]
+use tokio_rustls::TlsAcceptor;
+
+#[cfg(unix)]
+use super::bind_unix;
+#[cfg(feature = "tls")]
+use super::TlsCertificates;
+use super::{
+    AnyAuthenticator, Authenticator, EventHandler, Listener, OriginPolicy, Router, Server,
+};
+
+#[derive(Clone)]
+pub(crate) struct ServerConfig {
+    pub(crate) max_frame_size: usize,
+    pub(crate) max_message_size: usize,
+    pub(crate) validate_utf8: bool,
+    pub(crate) read_buffer_size: usize,
+    pub(crate) handshake_timeout: Duration,
+    pub(crate) idle_timeout: Option<Duration>,
+    pub(crate) max_connections: Option<usize>,
+    pub(crate) max_headers: usize,
+    pub(crate) ping_interval: Option<Duration>,
+    pub(crate) max_missed_pongs: u32,
+    pub(crate) shutdown_grace_period: Duration,
+    pub(crate) socket_mode: Option<u32>,
+    pub(crate) subprotocols: Vec<String>,
+    pub(crate) require_subprotocol: bool,
+    pub(crate) extensions: Vec<Arc<dyn Extension>>,
+    pub(crate) origin_policy: Option<OriginPolicy>,
+    pub(crate) authenticator: Option<Arc<dyn AnyAuthenticator>>,
+    #[cfg(feature = "tls")]
+    pub(crate) tls: Option<TlsAcceptor>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            max_frame_size: MAX_PAYLOAD_SIZE,
+            max_message_size: MAX_PAYLOAD_SIZE,
+            validate_utf8: true,
+            read_buffer_size: BUFFER_SIZE,
+            handshake_timeout: HANDSHAKE_TIMEOUT,
+            idle_timeout: None,
+            max_connections: None,
+            max_headers: MAX_HEADERS,
+            ping_interval: None,
+            max_missed_pongs: MAX_MISSED_PONGS,
+            shutdown_grace_period: SHUTDOWN_GRACE_PERIOD,
+            socket_mode: None,
+            subprotocols: Vec::new(),
+            require_subprotocol: false,
+            extensions: Vec::new(),
+            origin_policy: None,
+            authenticator: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+}
+
+pub struct ServerBuilder {
+    url: String,
+    config: ServerConfig,
+    router: Router,
+}
+
+impl ServerBuilder {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            config: ServerConfig::default(),
+            router: Router::new(),
+        }
+    }
+
+    pub fn handler<H>(mut self, handler: Box<H>) -> Self
+    where
+        H: EventHandler + ?Sized + 'static,
+    {
+        self.router = self.router.fallback(handler);
+        self
+    }
+
+    pub fn router(mut self, router: Router) -> Self {
+        self.router = router;
+        self
+    }
+
+    pub fn max_frame_size(mut self, size: usize) -> Self {
+        self.config.max_frame_size = size;
+        self
+    }
+
+    pub fn max_message_size(mut self, size: usize) -> Self {
+        self.config.max_message_size = size;
+        self
+    }
+
+    // Invalid Text messages close the connection with 1007 unless disabled,
+    // in which case invalid sequences are replaced
+    pub fn validate_utf8(mut self, validate: bool) -> Self {
+        self.config.validate_utf8 = validate;
+        self
+    }
+
+    pub fn read_buffer_size(mut self, size: usize) -> Self {
+        self.config.read_buffer_size = size.max(1);
+        self
+    }
+
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.config.handshake_timeout = timeout;
+        self
+    }
+
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.config.idle_timeout = Some(timeout);
+        self
+    }
+
+    pub fn max_connections(mut self, count: usize) -> Self {
+        self.config.max_connections = Some(count);
+        self
+    }
+
+    pub fn max_headers(mut self, count: usize) -> Self {
+        self.config.max_headers = count;
+        self
+    }
+
+    pub fn ping_interval(mut self, interval: Duration) -> Self {
+        self.config.ping_interval = Some(interval);
+        self
+    }
+
+    pub fn max_missed_pongs(mut self, count: u32) -> Self {
+        self.config.max_missed_pongs = count;
+        self
+    }
+
+    pub fn shutdown_grace_period(mut self, grace_period: Duration) -> Self {
+        self.config.shutdown_grace_period = grace_period;
+        self
+    }
+
+    // Listed in preference order, the first one offered by the client is used
+    pub fn subprotocols<I, S>(mut self, protocols: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config.subprotocols = protocols.into_iter().map(Into::into).collect();
+        self
+    }
+
+    // Answer 400 instead of connecting without a subprotocol when none matches
+    pub fn require_subprotocol(mut self, require: bool) -> Self {
+        self.config.require_subprotocol = require;
+        self
+    }
+
+    // Handshakes from origins the policy rejects are answered with 403
+    pub fn origin_policy(mut self, policy: OriginPolicy) -> Self {
+        self.config.origin_policy = Some(policy);
+        self
+    }
+
+    pub fn authenticator<A: Authenticator + 'static>(mut self, authenticator: A) -> Self {
+        self.config.authenticator = Some(Arc::new(authenticator));
+        self
+    }
+
+    // Extensions are offered to the client in the order they are added
+    pub fn extension(mut self, extension: Box<dyn Extension>) -> Self {
+        self.config.extensions.push(Arc::from(extension));
+        self
+    }
+
+    #[cfg(feature = "deflate")]
+    pub fn deflate(self, config: DeflateConfig) -> Self {
+        self.extension(Box::new(config))
+    }
+
+    // Permissions of the socket file created by `build_unix`, e.g. 0o660. They
+    // are set before the socket appears at its path.
+    pub fn socket_mode(mut self, mode: u32) -> Self {
+        self.config.socket_mode = Some(mode);
+        self
+    }
+
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, certificates: TlsCertificates) -> Result<Self> {
+        self.config.tls = Some(certificates.acceptor()?);
+        Ok(self)
+    }
+
+    pub async fn build(self) -> Result<Server> {
+        Server::bind(&self.url, self.config, self.router).await
+    }
+
+    // The url is ignored, the server listens on the Unix socket at `path`
+    #[cfg(unix)]
+    pub async fn build_unix(self, path: impl AsRef<Path>) -> Result<Server<UnixListener>> {
+        let listener: UnixListener = bind_unix(path.as_ref(), self.config.socket_mode).await?;
+        Ok(self.build_with(listener))
+    }
+
+    // The url is ignored, connections come from `listener` instead
+    pub fn build_with<L: Listener>(self, listener: L) -> Server<L> {
+        Server::with_config(listener, self.config, self.router)
+    }
+}
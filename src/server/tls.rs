@@ -0,0 +1,319 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    io::BufReader,
+    path::Path,
+    sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use tokio_rustls::{
+    rustls::{
+        crypto::ring::{default_provider, sign::any_supported_type},
+        pki_types::{CertificateDer, PrivateKeyDer},
+        server::{ClientHello, ResolvesServerCert},
+        sign::CertifiedKey,
+        ServerConfig,
+    },
+    TlsAcceptor,
+};
+
+use crate::error::{Error, Result};
+
+#[derive(Default)]
+struct Store {
+    default: Option<Arc<CertifiedKey>>,
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+}
+
+// Certificates are looked up on every handshake, so replacing one takes
+// effect for new connections without restarting the server
+#[derive(Clone, Default)]
+pub struct TlsCertificates {
+    store: Arc<RwLock<Store>>,
+}
+
+impl TlsCertificates {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_pem_files(cert_path: impl AsRef<Path>, key_path: impl AsRef<Path>) -> Result<Self> {
+        let certificates: Self = Self::new();
+        certificates.set_default(cert_path, key_path)?;
+        Ok(certificates)
+    }
+
+    pub fn set_default(
+        &self,
+        cert_path: impl AsRef<Path>,
+        key_path: impl AsRef<Path>,
+    ) -> Result<()> {
+        let key: Arc<CertifiedKey> = load_pem_files(cert_path, key_path)?;
+        self.write().default = Some(key);
+        Ok(())
+    }
+
+    pub fn set_default_pem(&self, cert_pem: &[u8], key_pem: &[u8]) -> Result<()> {
+        let key: Arc<CertifiedKey> = load_pem(cert_pem, key_pem)?;
+        self.write().default = Some(key);
+        Ok(())
+    }
+
+    // `server_name` may start with "*." to match any single subdomain
+    pub fn insert(
+        &self,
+        server_name: &str,
+        cert_path: impl AsRef<Path>,
+        key_path: impl AsRef<Path>,
+    ) -> Result<()> {
+        let key: Arc<CertifiedKey> = load_pem_files(cert_path, key_path)?;
+        self.write()
+            .by_name
+            .insert(server_name.to_ascii_lowercase(), key);
+        Ok(())
+    }
+
+    pub fn insert_pem(&self, server_name: &str, cert_pem: &[u8], key_pem: &[u8]) -> Result<()> {
+        let key: Arc<CertifiedKey> = load_pem(cert_pem, key_pem)?;
+        self.write()
+            .by_name
+            .insert(server_name.to_ascii_lowercase(), key);
+        Ok(())
+    }
+
+    pub fn remove(&self, server_name: &str) -> bool {
+        self.write()
+            .by_name
+            .remove(&server_name.to_ascii_lowercase())
+            .is_some()
+    }
+
+    pub(crate) fn acceptor(&self) -> Result<TlsAcceptor> {
+        let mut config: ServerConfig =
+            ServerConfig::builder_with_provider(Arc::new(default_provider()))
+                .with_safe_default_protocol_versions()
+                .map_err(|err| Error::Tls(err.to_string()))?
+                .with_no_client_auth()
+                .with_cert_resolver(Arc::new(self.clone()));
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+
+    fn lookup(&self, server_name: Option<&str>) -> Option<Arc<CertifiedKey>> {
+        let store: RwLockReadGuard<'_, Store> =
+            self.store.read().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(server_name) = server_name {
+            let server_name: String = server_name.to_ascii_lowercase();
+            if let Some(key) = store.by_name.get(&server_name) {
+                return Some(Arc::clone(key));
+            }
+
+            if let Some((_, parent)) = server_name.split_once('.') {
+                if let Some(key) = store.by_name.get(&format!("*.{}", parent)) {
+                    return Some(Arc::clone(key));
+                }
+            }
+        }
+
+        store.default.clone()
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Store> {
+        self.store.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for TlsCertificates {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let store: RwLockReadGuard<'_, Store> =
+            self.store.read().unwrap_or_else(|e| e.into_inner());
+        f.debug_struct("TlsCertificates")
+            .field("default", &store.default.is_some())
+            .field("server_names", &store.by_name.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl ResolvesServerCert for TlsCertificates {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        self.lookup(client_hello.server_name())
+    }
+}
+
+fn load_pem_files(
+    cert_path: impl AsRef<Path>,
+    key_path: impl AsRef<Path>,
+) -> Result<Arc<CertifiedKey>> {
+    let cert_pem: Vec<u8> = std::fs::read(cert_path)?;
+    let key_pem: Vec<u8> = std::fs::read(key_path)?;
+    load_pem(&cert_pem, &key_pem)
+}
+
+fn load_pem(cert_pem: &[u8], key_pem: &[u8]) -> Result<Arc<CertifiedKey>> {
+    let chain: Vec<CertificateDer<'static>> =
+        rustls_pemfile::certs(&mut BufReader::new(cert_pem)).collect::<std::io::Result<_>>()?;
+    if chain.is_empty() {
+        return Err(Error::Tls(String::from("no certificate found")));
+    }
+
+    let key: PrivateKeyDer<'static> = rustls_pemfile::private_key(&mut BufReader::new(key_pem))?
+        .ok_or_else(|| Error::Tls(String::from("no private key found")))?;
+    let signing_key = any_supported_type(&key).map_err(|err| Error::Tls(err.to_string()))?;
+
+    Ok(Arc::new(CertifiedKey::new(chain, signing_key)))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::AsyncReadExt,
+        net::{TcpListener, TcpStream},
+    };
+    use tokio_rustls::{
+        rustls::{pki_types::ServerName, ClientConfig, RootCertStore},
+        TlsConnector,
+    };
+
+    use super::*;
+    use crate::server::Server;
+
+    // Returns the certificate and key PEMs along with the certificate DER
+    fn self_signed(name: &str) -> (String, String, Vec<u8>) {
+        let generated = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+        (
+            generated.cert.pem(),
+            generated.signing_key.serialize_pem(),
+            generated.cert.der().to_vec(),
+        )
+    }
+
+    fn resolved(certificates: &TlsCertificates, server_name: Option<&str>) -> Option<Vec<u8>> {
+        certificates
+            .lookup(server_name)
+            .map(|key| key.cert[0].as_ref().to_vec())
+    }
+
+    #[test]
+    fn lookup_by_server_name() {
+        let (default_cert, default_key, default_der) = self_signed("localhost");
+        let (exact_cert, exact_key, exact_der) = self_signed("chat.example.com");
+        let (wildcard_cert, wildcard_key, wildcard_der) = self_signed("*.example.com");
+
+        let certificates: TlsCertificates = TlsCertificates::new();
+        certificates
+            .set_default_pem(default_cert.as_bytes(), default_key.as_bytes())
+            .unwrap();
+        certificates
+            .insert_pem(
+                "chat.example.com",
+                exact_cert.as_bytes(),
+                exact_key.as_bytes(),
+            )
+            .unwrap();
+        certificates
+            .insert_pem(
+                "*.example.com",
+                wildcard_cert.as_bytes(),
+                wildcard_key.as_bytes(),
+            )
+            .unwrap();
+
+        assert_eq!(
+            resolved(&certificates, Some("chat.example.com")),
+            Some(exact_der.clone())
+        );
+        assert_eq!(
+            resolved(&certificates, Some("CHAT.example.com")),
+            Some(exact_der)
+        );
+        assert_eq!(
+            resolved(&certificates, Some("api.example.com")),
+            Some(wildcard_der.clone())
+        );
+        // A wildcard only covers one label
+        assert_eq!(
+            resolved(&certificates, Some("a.b.example.com")),
+            Some(default_der.clone())
+        );
+        assert_eq!(
+            resolved(&certificates, Some("example.org")),
+            Some(default_der.clone())
+        );
+        assert_eq!(resolved(&certificates, None), Some(default_der));
+
+        assert!(certificates.remove("*.example.com"));
+        assert_ne!(
+            resolved(&certificates, Some("api.example.com")),
+            Some(wildcard_der)
+        );
+    }
+
+    #[test]
+    fn set_default_pem_replaces_the_certificate() {
+        let (first_cert, first_key, first_der) = self_signed("localhost");
+        let (second_cert, second_key, second_der) = self_signed("localhost");
+
+        let certificates: TlsCertificates = TlsCertificates::new();
+        assert_eq!(resolved(&certificates, None), None);
+
+        certificates
+            .set_default_pem(first_cert.as_bytes(), first_key.as_bytes())
+            .unwrap();
+        assert_eq!(resolved(&certificates, Some("localhost")), Some(first_der));
+
+        // Clones share the store, as the running acceptor does
+        let running: TlsCertificates = certificates.clone();
+        certificates
+            .set_default_pem(second_cert.as_bytes(), second_key.as_bytes())
+            .unwrap();
+        assert_eq!(resolved(&running, Some("localhost")), Some(second_der));
+    }
+
+    #[test]
+    fn invalid_pem_is_rejected() {
+        let certificates: TlsCertificates = TlsCertificates::new();
+        assert!(matches!(
+            certificates.set_default_pem(b"", b""),
+            Err(Error::Tls(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn refused_connections_are_answered_over_tls() {
+        let (cert, key, der) = self_signed("localhost");
+        let certificates: TlsCertificates = TlsCertificates::new();
+        certificates
+            .set_default_pem(cert.as_bytes(), key.as_bytes())
+            .unwrap();
+
+        // Every connection is over the limit
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = Server::builder("")
+            .max_connections(0)
+            .tls(certificates)
+            .unwrap()
+            .build_with(listener);
+        let running = tokio::spawn(server.run());
+
+        let mut roots: RootCertStore = RootCertStore::empty();
+        roots.add(CertificateDer::from(der)).unwrap();
+        let config: ClientConfig =
+            ClientConfig::builder_with_provider(Arc::new(default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+        let socket: TcpStream = TcpStream::connect(address).await.unwrap();
+        let mut stream = TlsConnector::from(Arc::new(config))
+            .connect(ServerName::try_from("localhost").unwrap(), socket)
+            .await
+            .unwrap();
+
+        let mut response: Vec<u8> = Vec::new();
+        let _ = stream.read_to_end(&mut response).await;
+        assert!(response.starts_with(b"HTTP/1.1 503"));
+        running.abort();
+    }
+}
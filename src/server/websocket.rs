@@ -0,0 +1,357 @@
+use std::{
+    any::Any,
+    collections::HashMap,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+};
+
+use futures_core::Stream;
+use futures_sink::Sink;
+use tokio::{
+    sync::{
+        mpsc::{channel, Receiver, Sender, UnboundedReceiver, UnboundedSender},
+        AcquireError, OwnedSemaphorePermit, Semaphore,
+    },
+    task::JoinHandle,
+};
+
+use crate::{
+    error::{Error, Result},
+    websocket_types::{CloseCode, CloseFrame, RequestStruct},
+};
+
+use super::{ConnectionHandle, ConnectionId, EventHandler, Identity, Message, Public};
+
+// Messages read ahead of the application, the connection stops reading once full
+const MESSAGE_BUFFER: usize = 32;
+
+// Messages a `WebSocketWriter` queues before `poll_ready` waits for the socket
+const SEND_BUFFER: usize = 32;
+
+type Acquire =
+    Pin<Box<dyn Future<Output = std::result::Result<OwnedSemaphorePermit, AcquireError>> + Send>>;
+
+// A connection yielded by `Server::incoming`, read as a `Stream` and written as a `Sink`
+#[derive(Debug)]
+pub struct WebSocket {
+    pub id: ConnectionId,
+    pub request: RequestStruct,
+    pub params: HashMap<String, String>,
+    pub protocol: Option<String>,
+    identity: Option<Identity>,
+    reader: WebSocketReader,
+    writer: WebSocketWriter,
+}
+
+impl WebSocket {
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(String::as_str)
+    }
+
+    pub fn identity<T: Any>(&self) -> Option<&T> {
+        self.identity.as_deref()?.downcast_ref::<T>()
+    }
+
+    pub fn handle(&self) -> ConnectionHandle {
+        self.writer.handle.clone()
+    }
+
+    // Both halves can be moved to different tasks
+    pub fn split(self) -> (WebSocketWriter, WebSocketReader) {
+        (self.writer, self.reader)
+    }
+}
+
+impl Stream for WebSocket {
+    type Item = Result<Message>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.reader).poll_next(cx)
+    }
+}
+
+impl Sink<Message> for WebSocket {
+    type Error = Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.writer).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, message: Message) -> Result<()> {
+        Pin::new(&mut self.writer).start_send(message)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.writer).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.writer).poll_close(cx)
+    }
+}
+
+// Ends once the connection is closed
+#[derive(Debug)]
+pub struct WebSocketReader {
+    receiver: Receiver<Result<Message>>,
+}
+
+impl Stream for WebSocketReader {
+    type Item = Result<Message>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+// Messages are queued to the connection's writer task, `poll_ready` waits once
+// the queue is full but flushing does not wait for them to reach the socket.
+// Clones share the same queue.
+pub struct WebSocketWriter {
+    handle: ConnectionHandle,
+    queue: Arc<Semaphore>,
+    acquire: Option<Acquire>,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl WebSocketWriter {
+    fn new(handle: ConnectionHandle, queue: Arc<Semaphore>) -> Self {
+        Self {
+            handle,
+            queue,
+            acquire: None,
+            permit: None,
+        }
+    }
+
+    pub fn handle(&self) -> ConnectionHandle {
+        self.handle.clone()
+    }
+}
+
+impl Clone for WebSocketWriter {
+    fn clone(&self) -> Self {
+        Self::new(self.handle.clone(), Arc::clone(&self.queue))
+    }
+}
+
+impl fmt::Debug for WebSocketWriter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebSocketWriter")
+            .field("handle", &self.handle)
+            .field("queued", &(SEND_BUFFER - self.queue.available_permits()))
+            .finish()
+    }
+}
+
+impl Sink<Message> for WebSocketWriter {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this: &mut Self = self.get_mut();
+        if !this.handle.is_connected() {
+            return Poll::Ready(Err(Error::Closed));
+        }
+        if this.permit.is_some() {
+            return Poll::Ready(Ok(()));
+        }
+
+        let queue: Arc<Semaphore> = Arc::clone(&this.queue);
+        let acquire: &mut Acquire = this
+            .acquire
+            .get_or_insert_with(|| Box::pin(queue.acquire_owned()));
+        let permit: std::result::Result<OwnedSemaphorePermit, AcquireError> =
+            ready!(acquire.as_mut().poll(cx));
+        this.acquire = None;
+        this.permit = Some(permit.map_err(|_| Error::Closed)?);
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, message: Message) -> Result<()> {
+        let this: &mut Self = self.get_mut();
+        match this.permit.take() {
+            Some(permit) => this.handle.send_reserved(message, permit),
+            None => this.handle.send(message),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    // Starts the closing handshake unless a Close frame was already sent
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        match self.handle.close(CloseCode::Normal, "") {
+            Ok(()) | Err(Error::Closed) => Poll::Ready(Ok(())),
+            Err(err) => Poll::Ready(Err(err)),
+        }
+    }
+}
+
+// Connections accepted by `Server::incoming`, the server stops when it is dropped
+#[derive(Debug)]
+pub struct Incoming {
+    receiver: UnboundedReceiver<WebSocket>,
+    task: JoinHandle<()>,
+}
+
+impl Incoming {
+    pub(crate) fn new(receiver: UnboundedReceiver<WebSocket>, task: JoinHandle<()>) -> Self {
+        Self { receiver, task }
+    }
+}
+
+impl Stream for Incoming {
+    type Item = WebSocket;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<WebSocket>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+impl Drop for Incoming {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+// Handler installed by `Server::incoming`, it forwards every message to the
+// `WebSocket` created for the connection
+pub(crate) struct Forward {
+    sockets: UnboundedSender<WebSocket>,
+}
+
+impl Forward {
+    pub(crate) fn new(sockets: UnboundedSender<WebSocket>) -> Self {
+        Self { sockets }
+    }
+}
+
+#[async_trait::async_trait]
+impl EventHandler for Forward {
+    type State = Sender<Result<Message>>;
+
+    async fn on_join(&self, request: RequestStruct, public: &mut Public) -> Self::State {
+        let (sender, receiver) = channel(MESSAGE_BUFFER);
+        let socket: WebSocket = WebSocket {
+            id: public.id,
+            request,
+            params: public.params.clone(),
+            protocol: public.protocol.clone(),
+            identity: public.identity.clone(),
+            reader: WebSocketReader { receiver },
+            writer: WebSocketWriter::new(
+                public.handle.clone(),
+                Arc::new(Semaphore::new(SEND_BUFFER)),
+            ),
+        };
+
+        // Nobody is left to accept the connection
+        if self.sockets.send(socket).is_err() {
+            let _ = public.close_with(CloseCode::GoingAway, "").await;
+        }
+        sender
+    }
+
+    async fn on_message(&self, sender: &mut Self::State, public: &mut Public) {
+        // Waiting here stops reading from the socket until the application catches up,
+        // the connection is closed once the reader half is gone
+        if let Some(message) = public.message.take() {
+            if sender.send(Ok(message)).await.is_err() {
+                let _ = public.close_with(CloseCode::GoingAway, "").await;
+            }
+        }
+    }
+
+    async fn on_close(&self, _sender: &mut Self::State, _close_frame: Option<CloseFrame>) {}
+
+    async fn on_error(&self, sender: &mut Self::State, error: Error) {
+        let _ = sender.send(Err(error)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{future::poll_fn, task::Waker};
+
+    use tokio::{
+        io::{duplex, AsyncReadExt, AsyncWriteExt},
+        sync::oneshot,
+    };
+
+    use super::*;
+    use crate::{
+        handshake::parse_request,
+        server::{write_outgoing, Router, Server},
+    };
+
+    async fn send(writer: &mut WebSocketWriter, message: &str) {
+        poll_fn(|cx| Pin::new(&mut *writer).poll_ready(cx))
+            .await
+            .unwrap();
+        Pin::new(&mut *writer)
+            .start_send(Message::text(message))
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn poll_ready_waits_for_the_socket() {
+        let (handle, receiver) = ConnectionHandle::new();
+        let mut writer: WebSocketWriter = WebSocketWriter::new(handle, Arc::new(Semaphore::new(2)));
+        send(&mut writer, "one").await;
+        send(&mut writer, "two").await;
+
+        // Nothing was written yet, the queue is full
+        let mut cx: Context<'_> = Context::from_waker(Waker::noop());
+        assert!(Pin::new(&mut writer).poll_ready(&mut cx).is_pending());
+
+        let (mut client, socket) = duplex(1024);
+        tokio::spawn(write_outgoing(socket, receiver, Default::default()));
+        send(&mut writer, "three").await;
+
+        let mut written: [u8; 17] = [0; 17];
+        client.read_exact(&mut written).await.unwrap();
+        assert_eq!(&written, b"\x81\x03one\x81\x03two\x81\x05three");
+    }
+
+    #[tokio::test]
+    async fn incoming_ends_with_the_server() {
+        let server: Server = Server::new("127.0.0.1:0").await.unwrap();
+        let (shutdown, stopped) = oneshot::channel::<()>();
+        let mut incoming: Incoming = server.incoming_until(async move {
+            let _ = stopped.await;
+        });
+
+        shutdown.send(()).unwrap();
+        let next: Option<WebSocket> = poll_fn(|cx| Pin::new(&mut incoming).poll_next(cx)).await;
+        assert!(next.is_none());
+    }
+
+    #[tokio::test]
+    async fn dropped_socket_closes_the_connection() {
+        let (sockets, mut accepted) = tokio::sync::mpsc::unbounded_channel();
+        let mut server: Server = Server::new("127.0.0.1:0").await.unwrap();
+        server.set_router(Router::new().fallback(Box::new(Forward::new(sockets))));
+
+        let request: RequestStruct = parse_request(
+            String::from("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n"),
+            16,
+        )
+        .unwrap();
+        let (mut client, stream) = duplex(1024);
+        tokio::spawn(async move { server.serve_upgraded(stream, request).await });
+        drop(accepted.recv().await.unwrap());
+
+        // A masked "hi" text frame
+        client
+            .write_all(&[0x81, 0x82, 0, 0, 0, 0, b'h', b'i'])
+            .await
+            .unwrap();
+        let mut close: [u8; 4] = [0; 4];
+        client.read_exact(&mut close).await.unwrap();
+        assert_eq!(close, [0x88, 0x02, 0x03, 0xe9]);
+    }
+}
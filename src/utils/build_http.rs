@@ -1,4 +1,7 @@
-use crate::websocket_types::ResponseStruct;
+use crate::{
+    http_types::HTTP_VERSION,
+    websocket_types::{RequestStruct, ResponseStruct},
+};
 
 pub fn build_response(data: ResponseStruct) -> String {
     let mut response_string: String = format!("{}\r\n", data.status.as_str());
@@ -8,3 +11,12 @@ pub fn build_response(data: ResponseStruct) -> String {
     response_string.push_str("\r\n");
     response_string
 }
+
+pub fn build_request(data: RequestStruct) -> String {
+    let mut request_string: String = format!("{} {} {}\r\n", data.method, data.uri, HTTP_VERSION);
+    for (key, value) in data.headers {
+        request_string.push_str(&format!("{}: {}\r\n", key, value));
+    }
+    request_string.push_str("\r\n");
+    request_string
+}
@@ -13,6 +13,13 @@ pub fn generate_key() -> String {
     key
 }
 
+pub fn generate_mask() -> [u8; 4] {
+    let mut rng: ThreadRng = thread_rng();
+    let mut mask: [u8; 4] = [0u8; 4];
+    rng.fill_bytes(&mut mask);
+    mask
+}
+
 pub fn generate_accept(key: String) -> String {
     let mut hasher = Sha1::new();
     let key_concatenate: String = format!("{}{}", key, WEBSOCKET_GUID);
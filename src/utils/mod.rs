@@ -1,6 +1,8 @@
 mod build_http;
 mod generate_key;
+mod percent_decode;
 mod unmask_payload;
 pub use build_http::*;
 pub use generate_key::*;
+pub use percent_decode::*;
 pub use unmask_payload::*;
@@ -0,0 +1,39 @@
+// Decodes `%XX` escapes and `+` as used in query strings, invalid escapes are kept as is
+pub fn percent_decode(value: &str) -> String {
+    decode(value, true)
+}
+
+// Same as `percent_decode` for path segments, where `+` is a literal character
+pub fn percent_decode_path(value: &str) -> String {
+    decode(value, false)
+}
+
+fn decode(value: &str, plus_as_space: bool) -> String {
+    let bytes: &[u8] = value.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i: usize = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => match bytes.get(i + 1..i + 3).and_then(hex_byte) {
+                Some(byte) => {
+                    decoded.push(byte);
+                    i += 2;
+                }
+                None => decoded.push(b'%'),
+            },
+            b'+' if plus_as_space => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+fn hex_byte(hex: &[u8]) -> Option<u8> {
+    if !hex.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()
+}
@@ -1,9 +1,20 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
 
-use crate::{http_types::HttpStatus, utils::generate_key};
+use crate::{
+    error::{Error, Result},
+    http_types::HttpStatus,
+    utils::{generate_key, percent_decode},
+};
 
 pub const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
 pub const BUFFER_SIZE: usize = 1024;
+pub const MAX_PAYLOAD_SIZE: usize = 64 * 1024 * 1024;
+pub const MAX_MISSED_PONGS: u32 = 3;
+pub const MAX_HANDSHAKE_SIZE: usize = 8 * 1024;
+pub const MAX_HEADERS: usize = 32;
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+pub const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+pub const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(10);
 
 #[derive(Debug, Clone)]
 pub struct RequestStruct {
@@ -21,7 +32,36 @@ impl RequestStruct {
         }
     }
 
-    fn default() -> Self {
+    pub fn header(&self, name: &str) -> Option<&String> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    // The uri without its query string
+    pub fn path(&self) -> &str {
+        self.uri.split(['?', '#']).next().unwrap_or_default()
+    }
+
+    pub fn query(&self, name: &str) -> Option<String> {
+        let query: &str = self.uri.split_once('?')?.1;
+        let query: &str = query.split('#').next().unwrap_or_default();
+
+        query.split('&').find_map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key) == name).then(|| percent_decode(value))
+        })
+    }
+
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        self.header("Cookie")?.split(';').find_map(|pair| {
+            let (key, value) = pair.trim().split_once('=')?;
+            (key == name).then_some(value)
+        })
+    }
+
+    pub(crate) fn client_default() -> Self {
         let mut headers: HashMap<String, String> = HashMap::new();
         headers.insert(String::from("Connection"), String::from("Upgrade"));
         headers.insert(String::from("Upgrade"), String::from("websocket"));
@@ -36,11 +76,26 @@ impl RequestStruct {
     }
 }
 
+impl Default for RequestStruct {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct ResponseStruct {
     pub status: HttpStatus,
     pub headers: HashMap<String, String>,
 }
 
+impl ResponseStruct {
+    pub fn header(&self, name: &str) -> Option<&String> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+}
+
 impl Default for ResponseStruct {
     fn default() -> Self {
         let mut headers: HashMap<String, String> = HashMap::new();
@@ -53,3 +108,180 @@ impl Default for ResponseStruct {
         }
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CloseCode {
+    Normal,
+    GoingAway,
+    ProtocolError,
+    Unsupported,
+    InvalidPayload,
+    PolicyViolation,
+    MessageTooBig,
+    MandatoryExtension,
+    InternalError,
+    ServiceRestart,
+    TryAgainLater,
+    BadGateway,
+    Custom(u16),
+}
+
+impl CloseCode {
+    pub fn from_u16(code: u16) -> Option<Self> {
+        match code {
+            1000 => Some(Self::Normal),
+            1001 => Some(Self::GoingAway),
+            1002 => Some(Self::ProtocolError),
+            1003 => Some(Self::Unsupported),
+            1007 => Some(Self::InvalidPayload),
+            1008 => Some(Self::PolicyViolation),
+            1009 => Some(Self::MessageTooBig),
+            1010 => Some(Self::MandatoryExtension),
+            1011 => Some(Self::InternalError),
+            1012 => Some(Self::ServiceRestart),
+            1013 => Some(Self::TryAgainLater),
+            1014 => Some(Self::BadGateway),
+            3000..=4999 => Some(Self::Custom(code)),
+            _ => None,
+        }
+    }
+
+    // Custom codes must stay in 3000-4999, reserved codes such as 1005 or
+    // 1006 and registered ones only fit the named variants
+    pub fn is_valid(&self) -> bool {
+        match self {
+            CloseCode::Custom(code) => (3000..=4999).contains(code),
+            _ => true,
+        }
+    }
+
+    pub fn as_u16(&self) -> u16 {
+        match self {
+            CloseCode::Normal => 1000,
+            CloseCode::GoingAway => 1001,
+            CloseCode::ProtocolError => 1002,
+            CloseCode::Unsupported => 1003,
+            CloseCode::InvalidPayload => 1007,
+            CloseCode::PolicyViolation => 1008,
+            CloseCode::MessageTooBig => 1009,
+            CloseCode::MandatoryExtension => 1010,
+            CloseCode::InternalError => 1011,
+            CloseCode::ServiceRestart => 1012,
+            CloseCode::TryAgainLater => 1013,
+            CloseCode::BadGateway => 1014,
+            CloseCode::Custom(code) => *code,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CloseFrame {
+    pub code: CloseCode,
+    pub reason: String,
+}
+
+impl CloseFrame {
+    pub fn new(code: CloseCode, reason: &str) -> Self {
+        Self {
+            code,
+            reason: reason.to_string(),
+        }
+    }
+
+    // An empty payload means the peer did not send a status code
+    pub fn from_bytes(data: &[u8]) -> Result<Option<Self>> {
+        match data.len() {
+            0 => Ok(None),
+            1 => Err(Error::Protocol(String::from("truncated close code"))),
+            _ => {
+                let code: u16 = u16::from_be_bytes([data[0], data[1]]);
+                let code: CloseCode = CloseCode::from_u16(code)
+                    .ok_or_else(|| Error::Protocol(format!("invalid close code {}", code)))?;
+                let reason: String = String::from_utf8(data[2..].to_vec())?;
+                Ok(Some(Self { code, reason }))
+            }
+        }
+    }
+
+    pub(crate) fn check_code(&self) -> Result<()> {
+        match self.code.is_valid() {
+            true => Ok(()),
+            false => Err(Error::Protocol(format!(
+                "close code {} cannot be sent",
+                self.code.as_u16()
+            ))),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = self.code.as_u16().to_be_bytes().to_vec();
+
+        // Control frame payloads are limited to 125 bytes, 2 of them being the code
+        let mut end: usize = self.reason.len().min(123);
+        while !self.reason.is_char_boundary(end) {
+            end -= 1;
+        }
+        bytes.extend_from_slice(&self.reason.as_bytes()[..end]);
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close_payload(code: u16, reason: &[u8]) -> Vec<u8> {
+        [&code.to_be_bytes()[..], reason].concat()
+    }
+
+    #[test]
+    fn valid_close_codes_are_parsed() {
+        for code in [
+            1000, 1001, 1002, 1003, 1007, 1008, 1009, 1010, 1011, 1012, 1013, 1014, 3000, 4999,
+        ] {
+            let close_frame: CloseFrame = CloseFrame::from_bytes(&close_payload(code, b"bye"))
+                .unwrap()
+                .unwrap();
+            assert_eq!(close_frame.code.as_u16(), code);
+            assert_eq!(close_frame.reason, "bye");
+        }
+
+        let close_frame = CloseFrame::from_bytes(&close_payload(1010, b"")).unwrap();
+        assert_eq!(close_frame.unwrap().code, CloseCode::MandatoryExtension);
+        assert_eq!(CloseFrame::from_bytes(&[]).unwrap(), None);
+    }
+
+    #[test]
+    fn reserved_close_codes_are_rejected() {
+        for code in [0, 999, 1004, 1005, 1006, 1015, 1016, 2999, 5000, u16::MAX] {
+            let result = CloseFrame::from_bytes(&close_payload(code, b""));
+            assert!(matches!(result, Err(Error::Protocol(_))), "{}", code);
+        }
+    }
+
+    #[test]
+    fn malformed_close_payloads() {
+        assert!(matches!(
+            CloseFrame::from_bytes(&[0x03]),
+            Err(Error::Protocol(_))
+        ));
+
+        let result = CloseFrame::from_bytes(&close_payload(1000, b"\xff\xfe"));
+        assert!(matches!(result, Err(Error::Utf8(_))));
+    }
+
+    #[test]
+    fn only_valid_codes_can_be_sent() {
+        assert!(CloseFrame::new(CloseCode::ServiceRestart, "")
+            .check_code()
+            .is_ok());
+        assert!(CloseFrame::new(CloseCode::Custom(3000), "")
+            .check_code()
+            .is_ok());
+
+        for code in [1000, 1005, 1006, 2999, 5000] {
+            let close_frame: CloseFrame = CloseFrame::new(CloseCode::Custom(code), "");
+            assert!(close_frame.check_code().is_err(), "{}", code);
+        }
+    }
+}